@@ -1,6 +1,5 @@
-
-use serde::{Serialize, Deserialize, ser::Error};
-use serde_json::{from_str, to_string_pretty};
+use serde::{Serialize, Serializer, Deserialize, Deserializer, de::DeserializeOwned, ser::Error};
+use serde_json::{from_str, to_string_pretty, from_value, to_value, Value, Map};
 
 use std::{
     io::{
@@ -8,12 +7,33 @@ use std::{
         Write
     },
     fs::File,
-    path::Path,
+    path::{
+        Path,
+        PathBuf
+    },
+    collections::BTreeMap,
+    marker::PhantomData,
     sync::{
         Arc,
-        RwLock
+        Mutex,
+        RwLock,
+        RwLockWriteGuard,
+        mpsc::{
+            channel,
+            Sender,
+            RecvTimeoutError
+        }
+    },
+    thread::JoinHandle,
+    cell::Cell,
+    time::{
+        Duration,
+        SystemTime
     },
-    fmt::Debug
+    fmt::{
+        Debug,
+        Display
+    }
 };
 
 use crate::{
@@ -22,20 +42,407 @@ use crate::{
         ProtectedAccess, 
         RwProvider
     },
-    error::ParsingError
+    version::Version,
+    error::{
+        ParsingError,
+        FormattingError,
+        ArgumentValueError,
+        ArgumentMissingError,
+        ConversionError,
+        OperationError,
+        ValidationError
+    },
+    log_error,
+    log_info,
+    log_warning
 };
+#[cfg(any(feature="toml", feature="yaml", feature="ron"))]
+use crate::error::FormatError;
 
 /// Represents the a specific set of configurations that can be stored in a file, and later retreived. 
-pub trait ConfigBase: Serialize + for <'a> Deserialize<'a> + Debug { }
+pub trait ConfigBase: Serialize + for <'a> Deserialize<'a> + Debug {
+    /// Checks that the values stored make sense, returning every problem found. This is run before a value is published by `open`, `reload`, or `LayeredLoader::load`.
+    /// By default, every value is considered valid.
+    fn validate(&self) -> Vec<ValidationError> {
+        Vec::new()
+    }
+    /// The schema version of this configuration, and the migrations used to upgrade older files to it. These are applied by `open` and `reload`.
+    /// By default, the configuration is not versioned.
+    fn migrations() -> Option<MigrationChain> where Self: Sized {
+        None
+    }
+}
+
+/// A function that upgrades the raw representation of a configuration from one schema version to the next.
+pub type MigrationFn = fn(&mut Value) -> Result<(), ParsingError>;
+
+/// The current schema version of a configuration, along with the migrations that upgrade older versions to it.
+#[derive(Clone)]
+pub struct MigrationChain {
+    field: String,
+    current: Version,
+    steps: Vec<(Version, Version, MigrationFn)>,
+    rewrite: bool
+}
+impl Debug for MigrationChain {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let steps: Vec<(Version, Version)> = self.steps.iter().map(|(from, to, _)| (*from, *to)).collect();
+        f.debug_struct("MigrationChain")
+            .field("field", &self.field)
+            .field("current", &self.current)
+            .field("steps", &steps)
+            .field("rewrite", &self.rewrite)
+            .finish()
+    }
+}
+impl MigrationChain {
+    /// Creates a chain for configurations that store their version in `field`, and are currently at version `current`. 
+    /// Files without the field are considered to be version `0.0.0`.
+    pub fn new<S: Into<String>>(field: S, current: Version) -> Self {
+        Self {
+            field: field.into(),
+            current,
+            steps: Vec::new(),
+            rewrite: false
+        }
+    }
+    /// Registers a migration that upgrades files at version `from` to version `to`.
+    /// # Panics
+    /// If `to` is not newer than `from`, this will panic.
+    pub fn migration(mut self, from: Version, to: Version, func: MigrationFn) -> Self {
+        if to <= from {
+            panic!("A migration must upgrade to a newer version ({from} -> {to}).");
+        }
+
+        self.steps.push((from, to, func));
+        self
+    }
+    /// If set, files that had to be migrated are rewritten in the current version after they are loaded.
+    pub fn rewrite(mut self, rewrite: bool) -> Self {
+        self.rewrite = rewrite;
+        self
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+    pub fn current(&self) -> Version {
+        self.current
+    }
+    pub fn rewrites(&self) -> bool {
+        self.rewrite
+    }
+
+    /// Determines the version stored in `raw`. The version can be stored as a string (`"1.2.0"`), or in the same form that `Version` is serialized.
+    pub fn version_of(&self, raw: &Value) -> Result<Version, ParsingError> {
+        match raw.get(&self.field) {
+            None => Ok(Version::new(0, 0, 0)),
+            Some(Value::String(s)) => s.parse().map_err(|e: FormattingError| ParsingError::Core(e.into())),
+            Some(v) => from_value(v.clone()).map_err(ParsingError::from)
+        }
+    }
+    /// Applies every migration required to bring `raw` up to the current version, in order. Returns true if any migration was applied.
+    pub fn apply(&self, raw: &mut Value) -> Result<bool, ParsingError> {
+        let mut version = self.version_of(raw)?;
+        if version > self.current {
+            return Err(ParsingError::Core(OperationError::new("migrate configuration", format!("version {version} is newer than the supported version {}", self.current)).into()));
+        }
+
+        let as_string = matches!(raw.get(&self.field), Some(Value::String(_)));
+        let mut migrated = false;
+        while version < self.current {
+            let (_, to, func) = self.steps
+                .iter()
+                .find(|(from, _, _)| *from == version)
+                .ok_or_else(|| ParsingError::Core(OperationError::new("migrate configuration", format!("no migration is registered from version {version}")).into()))?;
+
+            func(raw)?;
+            version = *to;
+            migrated = true;
+
+            let stored = if as_string { Value::String(version.to_string()) } else { to_value(version).map_err(ParsingError::from)? };
+            match raw.as_object_mut() {
+                Some(map) => {
+                    map.insert(self.field.clone(), stored);
+                },
+                None => return Err(ParsingError::Core(OperationError::new("migrate configuration", "the configuration is not an object").into()))
+            }
+        }
+
+        Ok(migrated)
+    }
+}
+
+/// The file formats that a configuration can be stored in. Formats other than JSON are enabled by their own cargo features (`toml`, `yaml`, `ron`).
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ConfigFormat {
+    #[default]
+    Json,
+    #[cfg(feature="toml")]
+    Toml,
+    #[cfg(feature="yaml")]
+    Yaml,
+    #[cfg(feature="ron")]
+    Ron
+}
+impl Display for ConfigFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+impl ConfigFormat {
+    /// The name of the format, such as `JSON`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "JSON",
+            #[cfg(feature="toml")]
+            Self::Toml => "TOML",
+            #[cfg(feature="yaml")]
+            Self::Yaml => "YAML",
+            #[cfg(feature="ron")]
+            Self::Ron => "RON"
+        }
+    }
+    /// Determines the format from a file extension (without the leading period). Returns `None` if the extension is unknown, or its feature is not enabled.
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_lowercase().as_str() {
+            "json" => Some(Self::Json),
+            #[cfg(feature="toml")]
+            "toml" => Some(Self::Toml),
+            #[cfg(feature="yaml")]
+            "yaml" | "yml" => Some(Self::Yaml),
+            #[cfg(feature="ron")]
+            "ron" => Some(Self::Ron),
+            _ => None
+        }
+    }
+    /// Determines the format from the extension of `path`, if it is known.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        path.as_ref()
+            .extension()
+            .and_then(|x| x.to_str())
+            .and_then(Self::from_extension)
+    }
+
+    /// Decodes `contents` in this format.
+    pub fn parse<T: DeserializeOwned>(&self, contents: &str) -> Result<T, ParsingError> {
+        match self {
+            Self::Json => from_str(contents).map_err(ParsingError::from),
+            #[cfg(feature="toml")]
+            Self::Toml => toml::from_str(contents).map_err(|e| {
+                let position = e.span().map(|x| line_column(contents, x.start));
+                FormatError::new(self.name(), e.message(), position).into()
+            }),
+            #[cfg(feature="yaml")]
+            Self::Yaml => serde_yaml::from_str(contents).map_err(|e| {
+                let position = e.location().map(|x| (x.line(), x.column()));
+                FormatError::new(self.name(), e.to_string(), position).into()
+            }),
+            #[cfg(feature="ron")]
+            Self::Ron => ron::from_str(contents).map_err(|e| {
+                FormatError::new(self.name(), e.code.to_string(), Some((e.position.line, e.position.col))).into()
+            })
+        }
+    }
+    /// Encodes `value` in this format, using the pretty printer where the format has one.
+    pub fn serialize<T: Serialize>(&self, value: &T) -> Result<String, ParsingError> {
+        match self {
+            Self::Json => to_string_pretty(value).map_err(ParsingError::from),
+            #[cfg(feature="toml")]
+            Self::Toml => toml::to_string_pretty(value).map_err(|e| FormatError::new(self.name(), e.to_string(), None).into()),
+            #[cfg(feature="yaml")]
+            Self::Yaml => serde_yaml::to_string(value).map_err(|e| FormatError::new(self.name(), e.to_string(), None).into()),
+            #[cfg(feature="ron")]
+            Self::Ron => ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default()).map_err(|e| FormatError::new(self.name(), e.to_string(), None).into())
+        }
+    }
+}
+
+/// Converts a byte offset into `contents` to a one-based line & column.
+#[cfg(feature="toml")]
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|x| *x != '\n').count() + 1;
+
+    (line, column)
+}
+
+/// The text written in place of an inline `Secret` when it is serialized.
+pub const REDACTED: &str = "<redacted>";
+
+thread_local! {
+    static EXPOSE_SECRETS: Cell<bool> = const { Cell::new(false) };
+}
+
+/// The error returned when saving a configuration that holds inline secrets.
+fn inline_secrets_error() -> ParsingError {
+    ParsingError::Core(OperationError::new("save configuration", "inline secrets cannot be saved, and should be moved to a file or environment variable").into())
+}
+
+/// Serializes `value` for saving. Inline secrets would be written as `REDACTED`, which can not be loaded again, so this fails if there are any.
+fn serialize_for_saving<V: Serialize>(format: ConfigFormat, value: &V) -> Result<String, ParsingError> {
+    let contents = format.serialize(value)?;
+    if contents != with_secrets_exposed(|| format.serialize(value))? {
+        return Err(inline_secrets_error());
+    }
+
+    Ok(contents)
+}
+
+/// Restores the previous value of `EXPOSE_SECRETS` when dropped, including while unwinding.
+struct ExposeGuard(bool);
+impl Drop for ExposeGuard {
+    fn drop(&mut self) {
+        EXPOSE_SECRETS.with(|x| x.set(self.0));
+    }
+}
+
+/// Runs `f` with inline secrets being serialized in plain text on this thread. Used for in-memory round trips through the serialized form.
+fn with_secrets_exposed<R, F: FnOnce() -> R>(f: F) -> R {
+    let _guard = ExposeGuard(EXPOSE_SECRETS.with(|x| x.replace(true)));
+    f()
+}
+
+/// Where the value of a `Secret` came from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum SecretSource {
+    /// The value was written directly in the configuration.
+    Inline,
+    /// The value was read from a file, written as `{ "file": "<path>" }`.
+    File(PathBuf),
+    /// The value was read from an environment variable, written as `{ "env": "<name>" }`.
+    Env(String)
+}
+
+/// A configuration value that is never printed or saved in plain text. 
+///
+/// `Debug` always prints a redaction. When serialized, secrets that came from a file or environment variable write that reference back, while inline secrets write `REDACTED`.
+/// As inline secrets would not survive being saved, saving a configuration that holds them fails, so secrets that must be saved should be stored in a file or environment variable.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Secret<T> {
+    value: T,
+    source: SecretSource
+}
+impl<T> Debug for Secret<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Secret({REDACTED})")
+    }
+}
+impl<T> Serialize for Secret<T> where T: Serialize {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        match &self.source {
+            SecretSource::Inline if EXPOSE_SECRETS.with(|x| x.get()) => self.value.serialize(serializer),
+            SecretSource::Inline => serializer.serialize_str(REDACTED),
+            SecretSource::File(path) => {
+                let mut map = Map::new();
+                map.insert("file".to_string(), Value::String(path.to_string_lossy().into_owned()));
+                map.serialize(serializer)
+            },
+            SecretSource::Env(name) => {
+                let mut map = Map::new();
+                map.insert("env".to_string(), Value::String(name.clone()));
+                map.serialize(serializer)
+            }
+        }
+    }
+}
+impl<'de, T> Deserialize<'de> for Secret<T> where T: DeserializeOwned {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        use serde::de::Error as _;
+
+        let raw = Value::deserialize(deserializer)?;
+
+        if let Value::Object(map) = &raw && map.len() == 1 {
+            match map.iter().next() {
+                Some((key, Value::String(path))) if key == "file" => return Self::from_file(path).map_err(D::Error::custom),
+                Some((key, Value::String(name))) if key == "env" => return Self::from_env(name).map_err(D::Error::custom),
+                _ => ()
+            }
+        }
+        if raw.as_str() == Some(REDACTED) {
+            return Err(D::Error::custom("the secret was redacted when it was saved, and must be provided again"));
+        }
+
+        from_value(raw).map(Self::new).map_err(D::Error::custom)
+    }
+}
+impl<T> Secret<T> {
+    /// Creates an inline secret.
+    pub fn new(value: T) -> Self {
+        Self {
+            value,
+            source: SecretSource::Inline
+        }
+    }
+    /// Reads the secret from the contents of a file. Trailing line breaks are ignored.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self, ParsingError> where T: DeserializeOwned {
+        let contents = std::fs::read_to_string(&path).map_err(ParsingError::from)?;
+
+        Ok(Self {
+            value: resolve_secret(&contents, &path.as_ref().to_string_lossy())?,
+            source: SecretSource::File(path.as_ref().to_path_buf())
+        })
+    }
+    /// Reads the secret from an environment variable.
+    pub fn from_env(name: &str) -> Result<Self, ParsingError> where T: DeserializeOwned {
+        let contents = std::env::var(name).map_err(|_| ParsingError::Core(ArgumentMissingError::new(name).into()))?;
+
+        Ok(Self {
+            value: resolve_secret(&contents, name)?,
+            source: SecretSource::Env(name.to_string())
+        })
+    }
+
+    /// Gets the value of the secret. Care should be taken to not log this.
+    pub fn expose(&self) -> &T {
+        &self.value
+    }
+    pub fn expose_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+    pub fn source(&self) -> &SecretSource {
+        &self.source
+    }
+}
+
+/// Decodes the text of a secret, first as a plain string, and then as JSON (so that numbers & other values can be stored).
+fn resolve_secret<T: DeserializeOwned>(contents: &str, from: &str) -> Result<T, ParsingError> {
+    let contents = contents.trim_end_matches(['\n', '\r']);
+
+    from_value(Value::String(contents.to_string()))
+        .or_else(|_| from_str(contents))
+        .map_err(|_| ParsingError::Core(ConversionError::new(format!("secret from '{from}'"), "the value could not be decoded").into()))
+}
+
+/// A function that is called after the configuration is replaced, receiving the old value (if any) and the new value.
+pub type ChangeCallback<T> = Box<dyn Fn(Option<&T>, &T) + Send + Sync>;
 
 /// A structure that can be stored in a static variable, and provides configuration access. This follows the provider pattern.
+/// Cloning the provider produces a new handle to the same configuration.
 pub struct ConfigurationProvider<T> where T: ConfigBase {
-    data: Arc<RwLock<Option<T>>>
+    data: Arc<RwLock<Option<T>>>,
+    source: Arc<Mutex<Option<(PathBuf, ConfigFormat)>>>,
+    callbacks: Arc<Mutex<Vec<ChangeCallback<T>>>>
 }
 impl<T> Default for ConfigurationProvider<T> where T: ConfigBase {
     fn default() -> Self {
         Self {
-            data: Arc::new(RwLock::new(None))
+            data: Arc::new(RwLock::new(None)),
+            source: Arc::new(Mutex::new(None)),
+            callbacks: Arc::new(Mutex::new(Vec::new()))
+        }
+    }
+}
+impl<T> Clone for ConfigurationProvider<T> where T: ConfigBase {
+    fn clone(&self) -> Self {
+        Self {
+            data: Arc::clone(&self.data),
+            source: Arc::clone(&self.source),
+            callbacks: Arc::clone(&self.callbacks)
         }
     }
 }
@@ -45,38 +452,1369 @@ impl<T> RwProvider for ConfigurationProvider<T> where T: ConfigBase {
         ProtectedAccess::new(&self.data)
     }
 }
-impl<T> OptionRwProvider<T> for ConfigurationProvider<T> where T: ConfigBase { }
+/// `pass` and `replace` go through the same path as loading, so changes made with them are logged and reported to the callbacks.
+impl<T> OptionRwProvider<T> for ConfigurationProvider<T> where T: ConfigBase {
+    fn pass(&self, value: T) {
+        self.publish(value);
+    }
+    fn replace(&self, value: T) -> Option<T> {
+        self.publish(value)
+    }
+}
 impl<T> ConfigurationProvider<T> where T: ConfigBase {
-    /// Reads the configuration file and returns any errors from IO or the parsing. 
+    /// Reads the configuration file and returns any errors from IO or the parsing. The format is determined by the file extension, defaulting to JSON.
+    /// The path is remembered, so that the configuration can later be reloaded with `reload` or `watch`.
     pub fn open<P: AsRef<Path>>(&self, path: P) -> Result<(), ParsingError> {
+        let format = ConfigFormat::from_path(&path).unwrap_or_default();
+        self.open_with(path, format)
+    }
+    /// Reads the configuration file using a specific format, and returns any errors from IO or the parsing.
+    pub fn open_with<P: AsRef<Path>>(&self, path: P, format: ConfigFormat) -> Result<(), ParsingError> {
+        let result = Self::read_path(path.as_ref(), format)?;
+
+        self.set_source(path.as_ref(), format);
+        self.publish(result);
+        Ok(())
+    }
+    /// Writes the configuration to the file system, and returns any conversions or IO errors. 
+    /// The format is determined by the file extension, falling back to the format that the configuration was opened with.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ParsingError> {
+        let format = ConfigFormat::from_path(&path)
+            .or_else(|| self.format())
+            .unwrap_or_default();
+        self.save_with(path, format)
+    }
+    /// Writes the configuration to the file system using a specific format, and returns any conversions or IO errors.
+    pub fn save_with<P: AsRef<Path>>(&self, path: P, format: ConfigFormat) -> Result<(), ParsingError> {
+        self.save_with_backups(path, format, 0)
+    }
+    /// Writes the configuration to the file system using a specific format, keeping up to `backups` previous versions of the file.
+    /// The most recent backup is named `<file>.1.bak`, and the oldest is `<file>.<backups>.bak`.
+    ///
+    /// The contents are written to a temporary file next to `path`, which then replaces the original. If there is no configuration loaded, or it is poisoned, the file is not touched.
+    pub fn save_with_backups<P: AsRef<Path>>(&self, path: P, format: ConfigFormat, backups: usize) -> Result<(), ParsingError> {
+        let contents = self.serialize(format)?;
+
+        write_atomic(path.as_ref(), &contents, backups).map_err(ParsingError::from)
+    }
+
+    /// The path that was last opened by this provider, if any.
+    pub fn path(&self) -> Option<PathBuf> {
+        self.source().map(|x| x.0)
+    }
+    /// The format of the file that was last opened by this provider, if any.
+    pub fn format(&self) -> Option<ConfigFormat> {
+        self.source().map(|x| x.1)
+    }
+    /// Re-reads the file that was last opened. The stored value is only replaced if the file could be read and parsed, otherwise the old value is kept.
+    pub fn reload(&self) -> Result<(), ParsingError> {
+        let (path, format) = self.source().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no configuration file has been opened"))?;
+        let result = Self::read_path(&path, format)?;
+
+        self.publish(result);
+        Ok(())
+    }
+    /// Reads a single field by its dotted path, such as `server.tls.cert_path`, going through the serialized form of the configuration. Array elements are addressed by their index.
+    /// Returns `ArgumentMissingError` if there is no field at the path, and `ConversionError` if it could not be decoded as `V`.
+    pub fn get<V: DeserializeOwned>(&self, path: &str) -> Result<V, ParsingError> {
+        let raw = self.raw()?;
+        let field = raw.pointer(&pointer_of(path)).ok_or_else(|| ParsingError::Core(ArgumentMissingError::new(path).into()))?;
+
+        from_value(field.clone()).map_err(|e| ParsingError::Core(ConversionError::new(path, e.to_string()).into()))
+    }
+    /// Replaces a single field by its dotted path, such as `server.port`. The field must already exist.
+    /// The new configuration is validated, and then published in the same way as `reload`, so change callbacks are called.
+    /// The write lock is held from reading the current value until the new one is published, so concurrent calls do not lose each other's changes.
+    pub fn set<V: Serialize>(&self, path: &str, value: V) -> Result<(), ParsingError> {
+        let guard = self.data.write().map_err(|_| ParsingError::Core(OperationError::new("set configuration", "the configuration is poisoned").into()))?;
+        let current = guard.as_ref().ok_or_else(|| ParsingError::Core(OperationError::new("access configuration", "no configuration is loaded").into()))?;
+
+        // Secrets have to be kept, as the value is rebuilt from its serialized form.
+        let mut raw = with_secrets_exposed(|| to_value(current)).map_err(ParsingError::from)?;
+        let field = raw.pointer_mut(&pointer_of(path)).ok_or_else(|| ParsingError::Core(ArgumentMissingError::new(path).into()))?;
+        *field = with_secrets_exposed(|| to_value(value)).map_err(ParsingError::from)?;
+
+        let result: T = from_value(raw).map_err(|e| ParsingError::Core(ConversionError::new(path, e.to_string()).into()))?;
+        self.publish_locked(guard, validated(result)?);
+        Ok(())
+    }
+    /// Registers a function that is called every time the configuration is replaced by `open`, `reload`, `set` or `pass`. 
+    /// The function is called while a read lock is held on the configuration, so it must not attempt to modify the provider.
+    pub fn on_change<F>(&self, callback: F) where F: Fn(Option<&T>, &T) + Send + Sync + 'static {
+        let mut callbacks = match self.callbacks.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+
+        callbacks.push(Box::new(callback));
+    }
+    /// Starts a background thread that checks the opened file every `interval`, and calls `reload` when its modification time changes.
+    /// The thread runs until the returned `ConfigWatcher` is stopped or dropped.
+    pub fn watch(&self, interval: Duration) -> Result<ConfigWatcher, ParsingError> where T: Send + Sync + 'static {
+        let path = self.path().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no configuration file has been opened"))?;
+        let mut last_modified = modified_time(&path);
+
+        let provider = self.clone();
+        let (sender, receiver) = channel::<()>();
+        let handle = std::thread::spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = receiver.recv_timeout(interval) {
+                let Some(path) = provider.path() else { continue };
+                let modified = modified_time(&path);
+                if modified.is_none() || modified == last_modified {
+                    continue;
+                }
+
+                last_modified = modified;
+                if let Err(e) = provider.reload() {
+                    log_error!("(Config) Unable to reload '{}' because of '{:?}'. The previous configuration is kept.", path.display(), e);
+                }
+            }
+        });
+
+        Ok(ConfigWatcher {
+            stop: Some(sender),
+            handle: Some(handle)
+        })
+    }
+
+    fn serialize(&self, format: ConfigFormat) -> Result<String, ParsingError> {
+        let guard = self.access();
+        if let Some(v) = guard.access() {
+            serialize_for_saving(format, v)
+        }
+        else if let Some(err) = guard.access_error() {
+            Err(serde_json::Error::custom(err).into())
+        }
+        else {
+            Err(ParsingError::Core(OperationError::new("save configuration", "no configuration is loaded").into()))
+        }
+    }
+    fn raw(&self) -> Result<Value, ParsingError> {
+        let guard = self.access();
+        if let Some(v) = guard.access() {
+            to_value(v).map_err(ParsingError::from)
+        }
+        else if let Some(err) = guard.access_error() {
+            Err(serde_json::Error::custom(err).into())
+        }
+        else {
+            Err(ParsingError::Core(OperationError::new("access configuration", "no configuration is loaded").into()))
+        }
+    }
+    fn read_path(path: &Path, format: ConfigFormat) -> Result<T, ParsingError> {
         let mut file = File::open(path).map_err(ParsingError::from)?;
 
         let mut contents = String::new();
         file.read_to_string(&mut contents).map_err(ParsingError::from)?;
 
-        let result: Result<T, _> = from_str(&contents);
-        let result = match result {
+        let (value, rewrite) = Self::decode(&contents, format)?;
+        if let Some(contents) = rewrite {
+            write_atomic(path, &contents, 0).map_err(ParsingError::from)?;
+        }
+
+        Ok(value)
+    }
+    /// Parses, migrates and validates the contents of a configuration file. Also returns the new contents if the file should be rewritten after migrating.
+    ///
+    /// Files containing inline secrets are never rewritten, as the secrets would be replaced by `REDACTED`.
+    fn decode(contents: &str, format: ConfigFormat) -> Result<(T, Option<String>), ParsingError> {
+        let Some(chain) = T::migrations() else {
+            return Ok((validated(format.parse(contents)?)?, None));
+        };
+
+        let mut raw: Value = format.parse(contents)?;
+        let migrated = chain.apply(&mut raw)?;
+        let value = validated(from_value(raw).map_err(ParsingError::from)?)?;
+        if !migrated || !chain.rewrites() {
+            return Ok((value, None));
+        }
+
+        match serialize_for_saving(format, &value) {
+            Ok(rewritten) => Ok((value, Some(rewritten))),
+            Err(_) => {
+                log_warning!("(Config) The migrated configuration was not rewritten, as it contains inline secrets.");
+                Ok((value, None))
+            }
+        }
+    }
+    fn source(&self) -> Option<(PathBuf, ConfigFormat)> {
+        match self.source.lock() {
+            Ok(v) => v.clone(),
+            Err(e) => e.into_inner().clone()
+        }
+    }
+    fn set_source(&self, path: &Path, format: ConfigFormat) {
+        let mut guard = match self.source.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+
+        *guard = Some((path.to_path_buf(), format));
+    }
+    /// Swaps in the new value, logs what changed, and then informs all callbacks of the change. Returns the old value, if any.
+    fn publish(&self, value: T) -> Option<T> {
+        let guard = match self.data.write() {
             Ok(v) => v,
-            Err(e) => return Err(e.into())
+            Err(e) => e.into_inner()
         };
+        self.publish_locked(guard, value)
+    }
+    /// Behaves like `publish`, using a write lock that is already held. 
+    /// The lock is downgraded, rather than released, after the value is stored, so that the callbacks receive exactly the value stored by this call.
+    fn publish_locked(&self, mut guard: RwLockWriteGuard<'_, Option<T>>, value: T) -> Option<T> {
+        let old = guard.replace(value);
+        self.data.clear_poison();
+        let guard = RwLockWriteGuard::downgrade(guard);
+
+        if let Some(new) = guard.as_ref() {
+            // The diff serializes both values, so it is done before other threads are blocked on the callbacks.
+            if let Some(old) = old.as_ref() && let Ok(changes) = diff(old, new) && !changes.is_empty() {
+                log_info!("(Config) {changes}");
+            }
+
+            let callbacks = match self.callbacks.lock() {
+                Ok(v) => v,
+                Err(e) => e.into_inner()
+            };
+            for callback in callbacks.iter() {
+                callback(old.as_ref(), new);
+            }
+        }
+
+        old
+    }
+}
+
+/// Runs `ConfigBase::validate` on `value`, returning it only if there were no problems.
+fn validated<T: ConfigBase>(value: T) -> Result<T, ParsingError> {
+    let errors = value.validate();
+    if errors.is_empty() {
+        Ok(value)
+    }
+    else {
+        Err(errors.into())
+    }
+}
+
+/// Writes `contents` to a temporary sibling of `path`, flushes it to the disk, and then renames it over `path`. 
+/// If `backups` is not zero, the existing file is first copied to `<file>.1.bak`, shifting older backups up to `<file>.<backups>.bak`.
+fn write_atomic(path: &Path, contents: &str, backups: usize) -> Result<(), std::io::Error> {
+    let name = path.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "the path does not name a file"))?;
+    let sibling = |suffix: String| {
+        let mut name = name.to_os_string();
+        name.push(suffix);
+        path.with_file_name(name)
+    };
+
+    let mut temp_name = std::ffi::OsString::from(".");
+    temp_name.push(name);
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+
+    let written = File::create(&temp)
+        .and_then(|mut file| {
+            // The new file keeps the permissions of the one it replaces. They are applied before anything is written, so that the contents are never more exposed.
+            match std::fs::metadata(path) {
+                Ok(metadata) => file.set_permissions(metadata.permissions())?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e)
+            }
+            file.write_all(contents.as_bytes())?;
+            file.sync_all()
+        });
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    if backups != 0 && path.exists() {
+        for i in (1..backups).rev() {
+            let from = sibling(format!(".{i}.bak"));
+            if from.exists() {
+                std::fs::rename(&from, sibling(format!(".{}.bak", i + 1)))?;
+            }
+        }
+
+        std::fs::copy(path, sibling(".1.bak".to_string()))?;
+    }
+
+    if let Err(e) = std::fs::rename(&temp, path) {
+        let _ = std::fs::remove_file(&temp);
+        return Err(e);
+    }
+
+    // Make sure that the rename itself is durable, where the platform allows it.
+    #[cfg(unix)]
+    if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+        File::open(parent)?.sync_all()?;
+    }
+
+    Ok(())
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|x| x.modified()).ok()
+}
+
+/// A handle to the background thread started by `ConfigurationProvider::watch`. Dropping this will stop the thread.
+pub struct ConfigWatcher {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>
+}
+impl Drop for ConfigWatcher {
+    fn drop(&mut self) {
+        self.stop_inner();
+    }
+}
+impl ConfigWatcher {
+    /// Stops the background thread, and waits for it to complete.
+    pub fn stop(mut self) {
+        self.stop_inner();
+    }
+    /// Determines if the background thread is still running.
+    pub fn is_running(&self) -> bool {
+        self.handle.as_ref().map(|x| !x.is_finished()).unwrap_or(false)
+    }
+
+    fn stop_inner(&mut self) {
+        if let Some(stop) = self.stop.take() {
+            let _ = stop.send(());
+        }
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+/// The way that a single field differs between two configurations.
+#[derive(Debug, PartialEq, Clone)]
+pub enum ChangeKind {
+    Added(Value),
+    Removed(Value),
+    Changed {
+        old: Value,
+        new: Value
+    }
+}
+
+/// A single field that differs between two configurations.
+#[derive(Debug, PartialEq, Clone)]
+pub struct ConfigChange {
+    path: String,
+    kind: ChangeKind
+}
+impl Display for ConfigChange {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.kind {
+            ChangeKind::Added(v) => write!(f, "+ {} = {}", &self.path, v),
+            ChangeKind::Removed(v) => write!(f, "- {} (was {})", &self.path, v),
+            ChangeKind::Changed { old, new } => write!(f, "~ {}: {} -> {}", &self.path, old, new)
+        }
+    }
+}
+impl ConfigChange {
+    /// The dotted path of the field.
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+    pub fn kind(&self) -> &ChangeKind {
+        &self.kind
+    }
+}
+
+/// Every field that differs between two configurations, in order of their paths. The `Display` form fits on a single line, for use with the logger.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct ConfigDiff {
+    changes: Vec<ConfigChange>
+}
+impl Display for ConfigDiff {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.changes.is_empty() {
+            return write!(f, "no fields changed");
+        }
+
+        write!(f, "{} field(s) changed: ", self.changes.len())?;
+        for (i, change) in self.changes.iter().enumerate() {
+            if i != 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{change}")?;
+        }
 
-        self.pass(result);
         Ok(())
     }
-    /// Writes the configuration to the file system, and returns any conversions or IO errors.
-    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), ParsingError> {
-        let mut file = File::create(path).map_err(ParsingError::from)?;
+}
+impl ConfigDiff {
+    pub fn changes(&self) -> &[ConfigChange] {
+        &self.changes
+    }
+    pub fn len(&self) -> usize {
+        self.changes.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+}
 
-        let guard = self.access();
-        if let Some(v) = guard.access() {
-            let contents = to_string_pretty(v).map_err(ParsingError::from)?;
+/// Compares two configurations through their serialized form. Arrays are compared as a whole, and secrets are compared in their redacted form.
+pub fn diff<T: Serialize>(old: &T, new: &T) -> Result<ConfigDiff, ParsingError> {
+    let mut old_fields = BTreeMap::new();
+    flatten(&to_value(old).map_err(ParsingError::from)?, "", &mut old_fields);
+    let mut new_fields = BTreeMap::new();
+    flatten(&to_value(new).map_err(ParsingError::from)?, "", &mut new_fields);
 
-            file.write_all(contents.as_bytes()).map_err(ParsingError::from)?;
+    let mut changes = Vec::new();
+    for (path, old) in &old_fields {
+        let kind = match new_fields.get(path) {
+            Some(new) if new == old => continue,
+            Some(new) => ChangeKind::Changed { old: old.clone(), new: new.clone() },
+            None => ChangeKind::Removed(old.clone())
+        };
+
+        changes.push(ConfigChange { path: path.clone(), kind });
+    }
+    for (path, new) in new_fields {
+        if !old_fields.contains_key(&path) {
+            changes.push(ConfigChange { path, kind: ChangeKind::Added(new) });
+        }
+    }
+
+    changes.sort_by(|a, b| a.path.cmp(&b.path));
+    Ok(ConfigDiff { changes })
+}
+
+/// The source of a value in a layered configuration. Layers are listed from the lowest to the highest priority.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash)]
+pub enum ConfigLayer {
+    Default,
+    System,
+    User,
+    Project,
+    Environment,
+    Override
+}
+
+/// Builds a configuration by merging several layers on top of each other. Each field of the final value comes from the highest priority layer that defines it.
+/// Objects are merged field by field, while all other values (including arrays) are replaced as a whole.
+pub struct LayeredLoader<T> where T: ConfigBase {
+    defaults: Option<Value>,
+    files: Vec<(ConfigLayer, PathBuf, ConfigFormat)>,
+    env_prefix: Option<String>,
+    overrides: Vec<String>,
+    _mark: PhantomData<T>
+}
+impl<T> Default for LayeredLoader<T> where T: ConfigBase {
+    fn default() -> Self {
+        Self {
+            defaults: None,
+            files: Vec::new(),
+            env_prefix: None,
+            overrides: Vec::new(),
+            _mark: PhantomData
+        }
+    }
+}
+impl<T> LayeredLoader<T> where T: ConfigBase {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the compiled-in default, used as the lowest layer.
+    pub fn defaults(mut self, value: &T) -> Result<Self, ParsingError> {
+        self.defaults = Some(with_secrets_exposed(|| to_value(value)).map_err(ParsingError::from)?);
+        Ok(self)
+    }
+    /// Adds a file to the specified layer. The format is determined by the file extension. Files that do not exist are skipped when loading.
+    /// `layer` should be one of `System`, `User`, or `Project`.
+    pub fn file<P: AsRef<Path>>(self, layer: ConfigLayer, path: P) -> Self {
+        let format = ConfigFormat::from_path(&path).unwrap_or_default();
+        self.file_with(layer, path, format)
+    }
+    /// Adds a file to the specified layer, using a specific format.
+    pub fn file_with<P: AsRef<Path>>(mut self, layer: ConfigLayer, path: P, format: ConfigFormat) -> Self {
+        self.files.push((layer, path.as_ref().to_path_buf(), format));
+        self
+    }
+    /// Reads environment variables starting with `prefix` when loading. 
+    /// The rest of the name is lowercased, and split into sections by `__`, so that with a prefix of `APP_`, `APP_SERVER__PORT` sets `server.port`.
+    pub fn env<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.env_prefix = Some(prefix.into());
+        self
+    }
+    /// Adds explicit overrides in the form `key=value`, where the key is a dotted path such as `server.port`. These are the highest priority layer.
+    pub fn overrides<I, S>(mut self, overrides: I) -> Self where I: IntoIterator<Item = S>, S: Into<String> {
+        self.overrides.extend(overrides.into_iter().map(Into::into));
+        self
+    }
+
+    /// Reads and merges every layer, and then decodes the result.
+    pub fn load(&self) -> Result<LayeredConfig<T>, ParsingError> {
+        let mut merged = Value::Object(Map::new());
+        let mut origins = BTreeMap::new();
+
+        if let Some(defaults) = self.defaults.as_ref() {
+            merge_layer(&mut merged, defaults.clone(), ConfigLayer::Default, "", &mut origins);
+        }
+
+        let mut files: Vec<&(ConfigLayer, PathBuf, ConfigFormat)> = self.files.iter().collect();
+        files.sort_by_key(|x| x.0);
+        let mut user_file = None;
+        for (layer, path, format) in files {
+            if *layer == ConfigLayer::User {
+                user_file = Some((path.clone(), *format));
+            }
+            if !path.exists() {
+                continue;
+            }
+
+            let contents = std::fs::read_to_string(path).map_err(ParsingError::from)?;
+            let value: Value = format.parse(&contents)?;
+            merge_layer(&mut merged, value, *layer, "", &mut origins);
+        }
+
+        // Raw values that were decoded as something other than a string, by their pointer, so that they can be retried as strings.
+        let mut coerced = BTreeMap::new();
+        let mut track = |path: &str, raw: &str| {
+            let value = parse_raw(raw);
+            match value.is_string() {
+                true => coerced.remove(&pointer_of(path)),
+                false => coerced.insert(pointer_of(path), raw.to_string())
+            };
+            nest(path, value)
+        };
+
+        if let Some(prefix) = self.env_prefix.as_deref() {
+            // Variables that are not valid unicode cannot be configuration values, so they are skipped.
+            let mut vars: Vec<(String, String)> = std::env::vars_os()
+                .filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)))
+                .filter(|(k, _)| k.starts_with(prefix) && k.len() > prefix.len())
+                .collect();
+            vars.sort();
+
+            for (key, raw) in vars {
+                let path = key[prefix.len()..]
+                    .split("__")
+                    .map(|x| x.to_lowercase())
+                    .collect::<Vec<_>>()
+                    .join(".");
+
+                merge_layer(&mut merged, track(&path, &raw), ConfigLayer::Environment, "", &mut origins);
+            }
+        }
+
+        for item in &self.overrides {
+            let (path, raw) = item.split_once('=').ok_or_else(|| ParsingError::Core(ArgumentValueError::new_display("overrides", item).into()))?;
+            merge_layer(&mut merged, track(path.trim(), raw.trim()), ConfigLayer::Override, "", &mut origins);
+        }
+
+        let value: T = decode_coerced(&mut merged, &coerced)?;
+        let value = validated(value)?;
+        let loaded = with_secrets_exposed(|| to_value(&value)).map_err(ParsingError::from)?;
+        Ok(LayeredConfig {
+            value,
+            loaded,
+            origins,
+            user_file
+        })
+    }
+}
+
+/// The result of `LayeredLoader::load`, which remembers the layer that each field came from.
+pub struct LayeredConfig<T> where T: ConfigBase {
+    value: T,
+    /// The serialized form of `value` as it was loaded (with secrets exposed), including the fields filled in by `serde` defaults, used to find what was changed since.
+    loaded: Value,
+    origins: BTreeMap<String, ConfigLayer>,
+    user_file: Option<(PathBuf, ConfigFormat)>
+}
+impl<T> Debug for LayeredConfig<T> where T: ConfigBase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // `loaded` is left out, as it holds inline secrets in plain text.
+        f.debug_struct("LayeredConfig")
+            .field("value", &self.value)
+            .field("origins", &self.origins)
+            .field("user_file", &self.user_file)
+            .finish()
+    }
+}
+impl<T> LayeredConfig<T> where T: ConfigBase {
+    pub fn value(&self) -> &T {
+        &self.value
+    }
+    pub fn value_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+    pub fn into_inner(self) -> T {
+        self.value
+    }
+
+    /// The layer that the field at the dotted `path` came from, if it was set by any layer.
+    pub fn origin(&self, path: &str) -> Option<ConfigLayer> {
+        self.origins.get(path).copied()
+    }
+    /// Every field that was set by a layer, along with the layer that set it.
+    pub fn origins(&self) -> impl Iterator<Item = (&str, ConfigLayer)> {
+        self.origins.iter().map(|(k, v)| (k.as_str(), *v))
+    }
+
+    /// Writes the user layer back to its file. This contains the fields that came from the user layer, along with any fields that were changed since loading.
+    /// Values from other layers are not written, so that they keep coming from their own layer.
+    pub fn save_user(&self) -> Result<(), ParsingError> {
+        let (path, format) = self.user_file.as_ref().ok_or_else(|| ParsingError::Core(OperationError::new("save user layer", "no user layer file was added").into()))?;
+
+        let mut current = BTreeMap::new();
+        flatten(&to_value(&self.value).map_err(ParsingError::from)?, "", &mut current);
+        let mut exposed = BTreeMap::new();
+        flatten(&with_secrets_exposed(|| to_value(&self.value)).map_err(ParsingError::from)?, "", &mut exposed);
+        let mut loaded = BTreeMap::new();
+        flatten(&self.loaded, "", &mut loaded);
+
+        let mut result = Value::Object(Map::new());
+        for (path, value) in exposed {
+            let from_user = self.origins.get(&path) == Some(&ConfigLayer::User);
+            if from_user || loaded.get(&path) != Some(&value) {
+                // Inline secrets from other layers are left out, but the ones that belong in this layer can not be saved.
+                if current.get(&path) != Some(&value) {
+                    return Err(inline_secrets_error());
+                }
+
+                merge_layer(&mut result, nest(&path, value), ConfigLayer::User, "", &mut BTreeMap::new());
+            }
+        }
+
+        let contents = format.serialize(&result)?;
+        write_atomic(path, &contents, 0).map_err(ParsingError::from)
+    }
+}
+
+/// Merges `layer` into `target`, recording the layer of each leaf that it sets into `origins`.
+fn merge_layer(target: &mut Value, layer: Value, source: ConfigLayer, prefix: &str, origins: &mut BTreeMap<String, ConfigLayer>) {
+    match (target, layer) {
+        (Value::Object(target), Value::Object(layer)) if !layer.is_empty() => {
+            for (key, value) in layer {
+                let path = join_path(prefix, &key);
+                let entry = target.entry(key).or_insert(Value::Null);
+                if !value.is_object() && entry.is_object() {
+                    origins.retain(|k, _| !k.starts_with(&format!("{path}.")));
+                }
+                if value.is_object() && !entry.is_object() {
+                    origins.remove(&path);
+                    *entry = Value::Object(Map::new());
+                }
+
+                merge_layer(entry, value, source, &path, origins);
+            }
+        }
+        (target, layer) => {
+            *target = layer;
+            origins.insert(prefix.to_string(), source);
+        }
+    }
+}
+/// Collects every leaf of `value` into `out`, keyed by its dotted path. Arrays and empty objects are considered leaves.
+fn flatten(value: &Value, prefix: &str, out: &mut BTreeMap<String, Value>) {
+    match value {
+        Value::Object(map) if !map.is_empty() => {
+            for (key, value) in map {
+                flatten(value, &join_path(prefix, key), out);
+            }
+        }
+        x => {
+            out.insert(prefix.to_string(), x.clone());
+        }
+    }
+}
+/// Wraps `value` in objects, so that it is located at the dotted `path`.
+fn nest(path: &str, value: Value) -> Value {
+    path.rsplit('.').fold(value, |acc, key| {
+        let mut map = Map::new();
+        map.insert(key.to_string(), acc);
+        Value::Object(map)
+    })
+}
+/// Converts a dotted path into a JSON pointer.
+fn pointer_of(path: &str) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+
+    path.split('.')
+        .map(|x| format!("/{}", x.replace('~', "~0").replace('/', "~1")))
+        .collect()
+}
+fn join_path(prefix: &str, key: &str) -> String {
+    if prefix.is_empty() {
+        key.to_string()
+    }
+    else {
+        format!("{prefix}.{key}")
+    }
+}
+/// Interprets a raw string as JSON if possible (numbers, booleans, arrays, etc.), otherwise as a string.
+fn parse_raw(raw: &str) -> Value {
+    from_str(raw).unwrap_or_else(|_| Value::String(raw.to_string()))
+}
+
+/// Decodes `merged`. If that fails, each value in `coerced` is put back as its raw string, keeping the change if it fixed (or changed) the error.
+/// This lets raw values such as `123` be decoded as strings when the field is a string.
+fn decode_coerced<T: DeserializeOwned>(merged: &mut Value, coerced: &BTreeMap<String, String>) -> Result<T, ParsingError> {
+    let mut error = match from_value(merged.clone()) {
+        Ok(v) => return Ok(v),
+        Err(e) => e
+    };
+
+    for (pointer, raw) in coerced {
+        let Some(field) = merged.pointer_mut(pointer) else { continue };
+        let parsed = std::mem::replace(field, Value::String(raw.clone()));
+
+        match from_value(merged.clone()) {
+            Ok(v) => return Ok(v),
+            Err(e) if e.to_string() != error.to_string() => error = e,
+            Err(_) => {
+                if let Some(field) = merged.pointer_mut(pointer) {
+                    *field = parsed;
+                }
+            }
+        }
+    }
+
+    Err(error.into())
+}
+
+#[cfg(feature="async")]
+mod config_async {
+    use tokio::fs::File as AsyncFile;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use super::*;
+
+    impl<T> ConfigurationProvider<T> where T: ConfigBase {
+        /// The async version of `open`. The file is read with tokio's file IO, and no lock is held across an await point.
+        pub async fn open_async<P: AsRef<Path>>(&self, path: P) -> Result<(), ParsingError> {
+            let format = ConfigFormat::from_path(&path).unwrap_or_default();
+            self.open_with_async(path, format).await
+        }
+        /// The async version of `open_with`.
+        pub async fn open_with_async<P: AsRef<Path>>(&self, path: P, format: ConfigFormat) -> Result<(), ParsingError> {
+            let result = Self::read_path_async(path.as_ref(), format).await?;
+
+            self.set_source(path.as_ref(), format);
+            self.publish(result);
             Ok(())
         }
-        else {
-            let err = guard.access_error().unwrap();
-            Err(serde_json::Error::custom(err).into())
+        /// The async version of `reload`.
+        pub async fn reload_async(&self) -> Result<(), ParsingError> {
+            let (path, format) = self.source().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no configuration file has been opened"))?;
+            let result = Self::read_path_async(&path, format).await?;
+
+            self.publish(result);
+            Ok(())
+        }
+
+        /// The async version of `save`. The configuration is serialized before any file IO starts, so the lock is never held across an await point.
+        pub async fn save_async<P: AsRef<Path>>(&self, path: P) -> Result<(), ParsingError> {
+            let format = ConfigFormat::from_path(&path)
+                .or_else(|| self.format())
+                .unwrap_or_default();
+            self.save_with_async(path, format).await
+        }
+        /// The async version of `save_with`.
+        pub async fn save_with_async<P: AsRef<Path>>(&self, path: P, format: ConfigFormat) -> Result<(), ParsingError> {
+            self.save_with_backups_async(path, format, 0).await
+        }
+        /// The async version of `save_with_backups`.
+        pub async fn save_with_backups_async<P: AsRef<Path>>(&self, path: P, format: ConfigFormat, backups: usize) -> Result<(), ParsingError> {
+            let contents = self.serialize(format)?;
+
+            write_atomic_async(path.as_ref(), &contents, backups).await.map_err(ParsingError::from)
         }
+
+        async fn read_path_async(path: &Path, format: ConfigFormat) -> Result<T, ParsingError> {
+            let mut file = AsyncFile::open(path).await.map_err(ParsingError::from)?;
+
+            let mut contents = String::new();
+            file.read_to_string(&mut contents).await.map_err(ParsingError::from)?;
+
+            let (value, rewrite) = Self::decode(&contents, format)?;
+            if let Some(contents) = rewrite {
+                write_atomic_async(path, &contents, 0).await.map_err(ParsingError::from)?;
+            }
+
+            Ok(value)
+        }
+    }
+
+    /// The async version of `write_atomic`.
+    async fn write_atomic_async(path: &Path, contents: &str, backups: usize) -> Result<(), std::io::Error> {
+        let name = path.file_name().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::InvalidInput, "the path does not name a file"))?;
+        let sibling = |suffix: String| {
+            let mut name = name.to_os_string();
+            name.push(suffix);
+            path.with_file_name(name)
+        };
+
+        let mut temp_name = std::ffi::OsString::from(".");
+        temp_name.push(name);
+        temp_name.push(".tmp");
+        let temp = path.with_file_name(temp_name);
+
+        let written = async {
+            let mut file = AsyncFile::create(&temp).await?;
+            match tokio::fs::metadata(path).await {
+                Ok(metadata) => file.set_permissions(metadata.permissions()).await?,
+                Err(e) if e.kind() == std::io::ErrorKind::NotFound => (),
+                Err(e) => return Err(e)
+            }
+            file.write_all(contents.as_bytes()).await?;
+            file.sync_all().await
+        }.await;
+        if let Err(e) = written {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e);
+        }
+
+        if backups != 0 && tokio::fs::try_exists(path).await? {
+            for i in (1..backups).rev() {
+                let from = sibling(format!(".{i}.bak"));
+                if tokio::fs::try_exists(&from).await? {
+                    tokio::fs::rename(&from, sibling(format!(".{}.bak", i + 1))).await?;
+                }
+            }
+
+            tokio::fs::copy(path, sibling(".1.bak".to_string())).await?;
+        }
+
+        if let Err(e) = tokio::fs::rename(&temp, path).await {
+            let _ = tokio::fs::remove_file(&temp).await;
+            return Err(e);
+        }
+
+        #[cfg(unix)]
+        if let Some(parent) = path.parent().filter(|x| !x.as_os_str().is_empty()) {
+            AsyncFile::open(parent).await?.sync_all().await?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use crate::error::{ArgumentMissingError, RangeError};
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct TestConfig {
+        name: String,
+        port: u16
+    }
+    impl ConfigBase for TestConfig {
+        fn validate(&self) -> Vec<ValidationError> {
+            let mut result = Vec::new();
+            if self.name.is_empty() {
+                result.push(ValidationError::new("name", ArgumentMissingError::new("name")));
+            }
+            if self.port == 0 {
+                result.push(ValidationError::new("port", RangeError::new("port", self.port, Some((1, u16::MAX)))));
+            }
+
+            result
+        }
+    }
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("exdisj-{}-{}", std::process::id(), name))
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn reload_config() {
+        let path = temp_path("reload.json");
+        std::fs::write(&path, r#"{ "name": "first", "port": 80 }"#).unwrap();
+
+        let provider: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        provider.open(&path).unwrap();
+
+        let changes = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&changes);
+        provider.on_change(move |old, new| {
+            assert_eq!(old.map(|x| x.port), Some(80));
+            assert_eq!(new.port, 8080);
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        std::fs::write(&path, r#"{ "name": "first", "port": 8080 }"#).unwrap();
+        provider.reload().unwrap();
+        assert_eq!(changes.load(Ordering::SeqCst), 1);
+
+        std::fs::write(&path, r#"{ "name": "#).unwrap();
+        assert!(provider.reload().is_err());
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+        assert_eq!(changes.load(Ordering::SeqCst), 1);
+
+        std::fs::write(&path, r#"{ "name": "", "port": 0 }"#).unwrap();
+        match provider.reload() {
+            Err(ParsingError::Validation(errors)) => {
+                let fields: Vec<&str> = errors.iter().map(|x| x.field()).collect();
+                assert_eq!(fields, vec!["name", "port"]);
+            },
+            x => panic!("expected validation errors, got {:?}", x)
+        }
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+
+        std::fs::remove_file(&path).unwrap();
+
+        // Values stored through the provider trait are published the same way.
+        let passed: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        let seen = Arc::new(Mutex::new(Vec::new()));
+        let record = Arc::clone(&seen);
+        passed.on_change(move |old, new| record.lock().unwrap().push((old.map(|x| x.port), new.port)));
+        passed.pass(TestConfig { name: "passed".to_string(), port: 80 });
+        assert_eq!(passed.replace(TestConfig { name: "passed".to_string(), port: 8080 }).map(|x| x.port), Some(80));
+        assert_eq!(*seen.lock().unwrap(), vec![(None, 80), (Some(80), 8080)]);
+    }
+
+    #[test]
+    fn watch_config() {
+        let path = temp_path("watch.json");
+        std::fs::write(&path, r#"{ "name": "watched", "port": 80 }"#).unwrap();
+
+        let provider: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        assert!(provider.watch(Duration::from_millis(10)).is_err());
+        provider.open(&path).unwrap();
+
+        let (sender, receiver) = channel();
+        let sender = Mutex::new(sender);
+        provider.on_change(move |_, new| {
+            let _ = sender.lock().unwrap().send(new.port);
+        });
+
+        let watcher = provider.watch(Duration::from_millis(10)).unwrap();
+        assert!(watcher.is_running());
+
+        std::fs::write(&path, r#"{ "name": "watched", "port": 8080 }"#).unwrap();
+        // The modification time is moved forward, in case the file system does not record the difference.
+        File::options().write(true).open(&path).unwrap().set_modified(SystemTime::now() + Duration::from_secs(5)).unwrap();
+
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)), Ok(8080));
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+
+        watcher.stop();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn dotted_access() {
+        let provider: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        assert!(provider.get::<u16>("port").is_err());
+
+        provider.pass(TestConfig { name: "test".to_string(), port: 80 });
+        assert_eq!(provider.get::<u16>("port").unwrap(), 80);
+        assert_eq!(provider.get::<String>("name").unwrap(), "test");
+        assert!(matches!(provider.get::<u16>("name"), Err(ParsingError::Core(crate::error::Error::Conv(_)))));
+        assert!(matches!(provider.get::<u16>("server.port"), Err(ParsingError::Core(crate::error::Error::ArgMiss(_)))));
+
+        provider.set("port", 8080).unwrap();
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+        assert!(provider.set("port", "abc").is_err());
+        assert!(provider.set("port", 0).is_err());
+        assert!(provider.set("address", "localhost").is_err());
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+
+        // Concurrent changes to different fields must both be kept.
+        let threads = ["name", "port"].map(|field| {
+            let provider = provider.clone();
+            std::thread::spawn(move || {
+                for i in 1..=50u16 {
+                    match field {
+                        "name" => provider.set(field, format!("name-{i}")).unwrap(),
+                        _ => provider.set(field, i).unwrap()
+                    }
+                }
+            })
+        });
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(provider.access().access(), Some(&TestConfig { name: "name-50".to_string(), port: 50 }));
+
+        let poisoned = provider.clone();
+        assert!(std::thread::spawn(move || {
+            let _guard = poisoned.data.write();
+            panic!("poisoning the configuration");
+        }).join().is_err());
+        assert!(matches!(provider.set("port", 80), Err(ParsingError::Core(crate::error::Error::Operation(_)))));
+    }
+
+    #[test]
+    fn secret_values() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Credentials {
+            user: String,
+            password: Secret<String>,
+            token: Secret<u64>
+        }
+        impl ConfigBase for Credentials { }
+
+        let token = temp_path("secret-token");
+        std::fs::write(&token, "1234\n").unwrap();
+        let config = format!(r#"{{ "user": "admin", "password": "hunter2", "token": {{ "file": {:?} }} }}"#, token.to_str().unwrap());
+
+        let provider: ConfigurationProvider<Credentials> = ConfigurationProvider::default();
+        provider.pass(ConfigFormat::Json.parse(&config).unwrap());
+        let printed = format!("{:?}", provider.access());
+        assert!(!printed.contains("hunter2") && !printed.contains("1234"));
+
+        provider.set("user", "root").unwrap();
+        {
+            let guard = provider.access();
+            let value = guard.access().unwrap();
+            assert_eq!(value.password.expose(), "hunter2");
+            assert_eq!(*value.token.expose(), 1234);
+            assert_eq!(value.token.source(), &SecretSource::File(token.clone()));
+        }
+
+        let saved: Value = from_str(&ConfigFormat::Json.serialize(provider.access().access().unwrap()).unwrap()).unwrap();
+        assert_eq!(saved["password"], REDACTED);
+        assert_eq!(saved["token"]["file"], token.to_str().unwrap());
+        assert!(provider.serialize(ConfigFormat::Json).is_err());
+
+        std::fs::remove_file(&token).unwrap();
+    }
+
+    #[test]
+    fn secret_round_trips() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Versioned {
+            version: Version,
+            password: Secret<String>
+        }
+        impl ConfigBase for Versioned {
+            fn migrations() -> Option<MigrationChain> {
+                let chain = MigrationChain::new("version", Version::new(1, 0, 0))
+                    .migration(Version::new(0, 0, 0), Version::new(1, 0, 0), |_| Ok(()))
+                    .rewrite(true);
+
+                Some(chain)
+            }
+        }
+
+        let path = temp_path("secret-migrate.json");
+        let original = r#"{ "password": "hunter2" }"#;
+        std::fs::write(&path, original).unwrap();
+
+        let provider: ConfigurationProvider<Versioned> = ConfigurationProvider::default();
+        provider.open(&path).unwrap();
+        assert_eq!(provider.access().access().unwrap().password.expose(), "hunter2");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+        std::fs::remove_file(&path).unwrap();
+
+        let defaults = Versioned { version: Version::new(1, 0, 0), password: Secret::new("hunter2".to_string()) };
+        let loaded = LayeredLoader::new().defaults(&defaults).unwrap().load().unwrap();
+        assert_eq!(loaded.value().password.expose(), "hunter2");
+
+        assert!(std::panic::catch_unwind(|| with_secrets_exposed(|| panic!("serializer failed"))).is_err());
+        assert_eq!(to_value(&defaults).unwrap()["password"], REDACTED);
+
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Credentials {
+            user: String,
+            password: Secret<String>
+        }
+        impl ConfigBase for Credentials { }
+
+        // Inline secrets are never saved, as the file could not be loaded again.
+        let path = temp_path("secret-save.json");
+        let original = r#"{ "user": "u", "password": "hunter2" }"#;
+        std::fs::write(&path, original).unwrap();
+        let provider: ConfigurationProvider<Credentials> = ConfigurationProvider::default();
+        provider.open(&path).unwrap();
+        assert!(provider.save(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), original);
+
+        // Secrets stored elsewhere survive being saved & opened again.
+        let secret = temp_path("secret-save-password");
+        std::fs::write(&secret, "hunter2").unwrap();
+        std::fs::write(&path, format!(r#"{{ "user": "u", "password": {{ "file": {:?} }} }}"#, secret.to_str().unwrap())).unwrap();
+        provider.open(&path).unwrap();
+        provider.set("user", "v").unwrap();
+        provider.save(&path).unwrap();
+        provider.open(&path).unwrap();
+        assert_eq!(provider.access().access().map(|x| (x.user.as_str(), x.password.expose().as_str())), Some(("v", "hunter2")));
+
+        // An inline secret from the defaults is not written into the user layer, so it can be loaded again.
+        let user = temp_path("secret-save-user.json");
+        std::fs::write(&user, r#"{ "user": "u" }"#).unwrap();
+        let defaults = Credentials { user: "default".to_string(), password: Secret::new("hunter2".to_string()) };
+        let loader = LayeredLoader::new().defaults(&defaults).unwrap().file(ConfigLayer::User, &user);
+        let mut loaded = loader.load().unwrap();
+        loaded.value_mut().user = "v".to_string();
+        loaded.save_user().unwrap();
+        assert_eq!(from_str::<Value>(&std::fs::read_to_string(&user).unwrap()).unwrap(), serde_json::json!({ "user": "v" }));
+        assert_eq!(loader.load().unwrap().value().password.expose(), "hunter2");
+
+        // Changing it would put it in the user layer, which can not be saved.
+        let mut loaded = loader.load().unwrap();
+        loaded.value_mut().password = Secret::new("changed".to_string());
+        assert!(loaded.save_user().is_err());
+        assert_eq!(from_str::<Value>(&std::fs::read_to_string(&user).unwrap()).unwrap(), serde_json::json!({ "user": "v" }));
+
+        for file in [path, secret, user] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn config_diff() {
+        let old = serde_json::json!({ "name": "a", "server": { "port": 80, "tls": true } });
+        let new = serde_json::json!({ "name": "a", "server": { "port": 8080 }, "debug": true });
+
+        let changes = diff(&old, &new).unwrap();
+        let paths: Vec<&str> = changes.changes().iter().map(|x| x.path()).collect();
+        assert_eq!(paths, vec!["debug", "server.port", "server.tls"]);
+        assert_eq!(changes.to_string(), "3 field(s) changed: + debug = true; ~ server.port: 80 -> 8080; - server.tls (was true)");
+
+        assert!(diff(&old, &old).unwrap().is_empty());
+    }
+
+    #[test]
+    fn atomic_save() {
+        let path = temp_path("save.json");
+        std::fs::write(&path, r#"{ "name": "old", "port": 80 }"#).unwrap();
+
+        let provider: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        assert!(provider.save(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), r#"{ "name": "old", "port": 80 }"#);
+
+        provider.pass(TestConfig { name: "new".to_string(), port: 8080 });
+        provider.save_with_backups(&path, ConfigFormat::Json, 2).unwrap();
+        provider.save_with_backups(&path, ConfigFormat::Json, 2).unwrap();
+
+        let backup = |i: usize| path.with_file_name(format!("{}.{i}.bak", path.file_name().unwrap().to_str().unwrap()));
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+            provider.save(&path).unwrap();
+            assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+        assert_eq!(std::fs::read_to_string(backup(2)).unwrap(), r#"{ "name": "old", "port": 80 }"#);
+        assert_eq!(std::fs::read_to_string(backup(1)).unwrap(), std::fs::read_to_string(&path).unwrap());
+        assert_eq!(ConfigFormat::Json.parse::<TestConfig>(&std::fs::read_to_string(&path).unwrap()).unwrap().port, 8080);
+
+        for file in [path.clone(), backup(1), backup(2)] {
+            std::fs::remove_file(file).unwrap();
+        }
+    }
+
+    #[test]
+    fn migrate_config() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Versioned {
+            version: Version,
+            name: String,
+            port: u16
+        }
+        impl ConfigBase for Versioned {
+            fn migrations() -> Option<MigrationChain> {
+                let chain = MigrationChain::new("version", Version::new(2, 0, 0))
+                    .migration(Version::new(0, 0, 0), Version::new(1, 0, 0), |raw| {
+                        raw["name"] = Value::String("unnamed".to_string());
+                        Ok(())
+                    })
+                    .migration(Version::new(1, 0, 0), Version::new(2, 0, 0), |raw| {
+                        let port = raw.as_object_mut().and_then(|x| x.remove("port_number")).unwrap_or(Value::from(80));
+                        raw["port"] = port;
+                        Ok(())
+                    })
+                    .rewrite(true);
+
+                Some(chain)
+            }
+        }
+
+        let path = temp_path("migrate.json");
+        std::fs::write(&path, r#"{ "port_number": 8080 }"#).unwrap();
+
+        let provider: ConfigurationProvider<Versioned> = ConfigurationProvider::default();
+        provider.open(&path).unwrap();
+        let expected = Versioned { version: Version::new(2, 0, 0), name: "unnamed".to_string(), port: 8080 };
+        assert_eq!(provider.access(), expected);
+
+        let rewritten: Versioned = ConfigFormat::Json.parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(rewritten, expected);
+
+        std::fs::write(&path, r#"{ "version": "3.0.0", "name": "a", "port": 1 }"#).unwrap();
+        assert!(provider.reload().is_err());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[cfg(feature="async")]
+    #[tokio::test]
+    async fn async_open_save() {
+        #[cfg(unix)]
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = temp_path("async.json");
+        std::fs::write(&path, r#"{ "name": "async", "port": 80 }"#).unwrap();
+
+        let provider: ConfigurationProvider<TestConfig> = ConfigurationProvider::default();
+        provider.open_async(&path).await.unwrap();
+        assert_eq!(provider.access(), TestConfig { name: "async".to_string(), port: 80 });
+
+        if let Some(v) = provider.access_mut().access() {
+            v.port = 8080;
+        }
+        #[cfg(unix)]
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600)).unwrap();
+        provider.save_async(&path).await.unwrap();
+        provider.reload_async().await.unwrap();
+        assert_eq!(provider.access().access().map(|x| x.port), Some(8080));
+        #[cfg(unix)]
+        assert_eq!(std::fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn config_formats() {
+        assert_eq!(ConfigFormat::from_path("config.JSON"), Some(ConfigFormat::Json));
+        assert_eq!(ConfigFormat::from_path("config"), None);
+
+        let err = ConfigFormat::Json.parse::<TestConfig>("{\n  \"name\": 4\n}").unwrap_err();
+        assert_eq!(err.position(), Some((2, 11)));
+
+        let value = TestConfig { name: "test".to_string(), port: 80 };
+        let encoded = ConfigFormat::Json.serialize(&value).unwrap();
+        assert_eq!(ConfigFormat::Json.parse::<TestConfig>(&encoded).unwrap(), value);
+    }
+
+    #[test]
+    fn layered_config() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Layered {
+            server: TestConfig,
+            debug: bool
+        }
+        impl ConfigBase for Layered { }
+
+        let user = temp_path("layered-user.json");
+        std::fs::write(&user, r#"{ "server": { "name": "user" } }"#).unwrap();
+
+        let defaults = Layered { server: TestConfig { name: "default".to_string(), port: 80 }, debug: false };
+        let mut loaded = LayeredLoader::new()
+            .defaults(&defaults).unwrap()
+            .file(ConfigLayer::System, temp_path("layered-missing.json"))
+            .file(ConfigLayer::User, &user)
+            .overrides(["server.port = 8080"])
+            .load()
+            .unwrap();
+
+        assert_eq!(loaded.value(), &Layered { server: TestConfig { name: "user".to_string(), port: 8080 }, debug: false });
+        assert_eq!(loaded.origin("server.name"), Some(ConfigLayer::User));
+        assert_eq!(loaded.origin("server.port"), Some(ConfigLayer::Override));
+        assert_eq!(loaded.origin("debug"), Some(ConfigLayer::Default));
+
+        loaded.value_mut().debug = true;
+        loaded.save_user().unwrap();
+        let saved: Value = from_str(&std::fs::read_to_string(&user).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "server": { "name": "user" }, "debug": true }));
+
+        std::fs::remove_file(&user).unwrap();
+
+        let loaded = LayeredLoader::<TestConfig>::new()
+            .overrides(["name = 123", "port = 8080"])
+            .load()
+            .unwrap();
+        assert_eq!(loaded.value(), &TestConfig { name: "123".to_string(), port: 8080 });
+    }
+
+    #[test]
+    fn layered_environment() {
+        #[derive(Debug, Serialize, Deserialize, PartialEq)]
+        struct Client {
+            name: String,
+            #[serde(default)]
+            timeout: u64,
+            proxy: Option<String>,
+            server: TestConfig
+        }
+        impl ConfigBase for Client { }
+
+        let prefix = format!("EXDISJ_TEST_{}_", std::process::id());
+        // SAFETY: no other test reads or writes variables with this prefix.
+        unsafe {
+            std::env::set_var(format!("{prefix}SERVER__PORT"), "9000");
+            std::env::set_var(format!("{prefix}SERVER__NAME"), "042");
+        }
+
+        let user = temp_path("layered-env-user.json");
+        std::fs::write(&user, r#"{ "name": "u" }"#).unwrap();
+
+        let mut loaded = LayeredLoader::<Client>::new()
+            .file(ConfigLayer::User, &user)
+            .env(&prefix)
+            .load()
+            .unwrap();
+        assert_eq!(loaded.value().server, TestConfig { name: "042".to_string(), port: 9000 });
+        assert_eq!(loaded.origin("server.port"), Some(ConfigLayer::Environment));
+        assert_eq!(loaded.origin("name"), Some(ConfigLayer::User));
+        assert_eq!(loaded.origin("timeout"), None);
+
+        // Fields that no layer set, and that were not changed, are not pinned into the user layer.
+        loaded.save_user().unwrap();
+        let saved: Value = from_str(&std::fs::read_to_string(&user).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "name": "u" }));
+
+        loaded.value_mut().timeout = 30;
+        loaded.save_user().unwrap();
+        let saved: Value = from_str(&std::fs::read_to_string(&user).unwrap()).unwrap();
+        assert_eq!(saved, serde_json::json!({ "name": "u", "timeout": 30 }));
+
+        unsafe {
+            std::env::remove_var(format!("{prefix}SERVER__PORT"));
+            std::env::remove_var(format!("{prefix}SERVER__NAME"));
+        }
+        std::fs::remove_file(&user).unwrap();
+    }
+
+    #[cfg(feature="toml")]
+    #[test]
+    fn toml_format() {
+        assert_eq!(ConfigFormat::from_path("config.toml"), Some(ConfigFormat::Toml));
+
+        let value: TestConfig = ConfigFormat::Toml.parse("name = \"test\"\nport = 80\n").unwrap();
+        assert_eq!(value, TestConfig { name: "test".to_string(), port: 80 });
+
+        let err = ConfigFormat::Toml.parse::<TestConfig>("name = \"test\"\nport = \"a\"\n").unwrap_err();
+        assert_eq!(err.position(), Some((2, 8)));
+    }
+
+    #[cfg(feature="yaml")]
+    #[test]
+    fn yaml_format() {
+        assert_eq!(ConfigFormat::from_path("config.yml"), Some(ConfigFormat::Yaml));
+
+        let value: TestConfig = ConfigFormat::Yaml.parse("name: test\nport: 80\n").unwrap();
+        assert_eq!(value, TestConfig { name: "test".to_string(), port: 80 });
+
+        let err = ConfigFormat::Yaml.parse::<TestConfig>("name: test\nport: a\n").unwrap_err();
+        assert_eq!(err.position(), Some((2, 7)));
+        assert!(matches!(&err, ParsingError::Format(x) if x.format() == "YAML"));
+    }
+
+    #[cfg(feature="ron")]
+    #[test]
+    fn ron_format() {
+        assert_eq!(ConfigFormat::from_path("config.ron"), Some(ConfigFormat::Ron));
+
+        let value: TestConfig = ConfigFormat::Ron.parse("(name: \"test\", port: 80)").unwrap();
+        assert_eq!(value, TestConfig { name: "test".to_string(), port: 80 });
+
+        let err = ConfigFormat::Ron.parse::<TestConfig>("(\n    name: \"test\",\n    port: \"a\",\n)").unwrap_err();
+        assert_eq!(err.position(), Some((3, 11)));
+        assert!(matches!(&err, ParsingError::Format(x) if x.format() == "RON"));
+    }
+}
@@ -110,6 +110,15 @@ impl<T> RangeError<T>  {
             range
         }
     }
+
+    /// Converts the value & range into a different type, keeping the target.
+    pub fn map<U, F: Fn(T) -> U>(self, f: F) -> RangeError<U> {
+        RangeError {
+            var: self.var,
+            val: f(self.val),
+            range: self.range.map(|(a, b)| (f(a), f(b)))
+        }
+    }
 }
 pub type IndexRangeError = RangeError<usize>;
 
@@ -366,19 +375,175 @@ impl Display for IOError {
     }
 }
 
-/// Represents errors that come from Serde JSON or IO. 
+/// An error produced by one of the non-JSON configuration formats. The format is stored by its name, such as `TOML`. The position, if known, is the one-based line & column of the error.
+#[derive(PartialEq, Eq, Clone)]
+pub struct FormatError {
+    format: &'static str,
+    message: String,
+    position: Option<(usize, usize)>
+}
+impl Debug for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.position {
+            Some((line, column)) => write!(f, "{} error at line {} column {}: '{}'", self.format, line, column, &self.message),
+            None => write!(f, "{} error: '{}'", self.format, &self.message)
+        }
+    }
+}
+impl Display for FormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn Debug).fmt(f)
+    }
+}
+impl FormatError {
+    pub fn new<T: Into<String>>(format: &'static str, message: T, position: Option<(usize, usize)>) -> Self {
+        Self {
+            format,
+            message: message.into(),
+            position
+        }
+    }
+
+    pub fn format(&self) -> &'static str {
+        self.format
+    }
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+    pub fn position(&self) -> Option<(usize, usize)> {
+        self.position
+    }
+}
+
+/// The reason that a single field of a configuration is invalid.
+#[derive(PartialEq, Eq, Clone)]
+pub enum ValidationIssue {
+    Range(RangeError<String>),
+    Value(ArgumentValueError),
+    Missing(ArgumentMissingError)
+}
+impl Debug for ValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let x: &dyn Debug = match self {
+            Self::Range(x) => x,
+            Self::Value(x) => x,
+            Self::Missing(x) => x
+        };
+
+        x.fmt(f)
+    }
+}
+impl<T> From<RangeError<T>> for ValidationIssue where T: Display {
+    fn from(value: RangeError<T>) -> Self {
+        Self::Range(value.map(|x| x.to_string()))
+    }
+}
+impl From<ArgumentValueError> for ValidationIssue {
+    fn from(value: ArgumentValueError) -> Self {
+        Self::Value(value)
+    }
+}
+impl From<ArgumentMissingError> for ValidationIssue {
+    fn from(value: ArgumentMissingError) -> Self {
+        Self::Missing(value)
+    }
+}
+
+/// A problem found while validating a configuration, tagged with the dotted path of the field it applies to.
+#[derive(PartialEq, Eq, Clone)]
+pub struct ValidationError {
+    field: String,
+    issue: ValidationIssue
+}
+impl Debug for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "the field '{}' is invalid: {:?}", &self.field, &self.issue)
+    }
+}
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        (self as &dyn Debug).fmt(f)
+    }
+}
+impl ValidationError {
+    pub fn new<T: Into<String>, U: Into<ValidationIssue>>(field: T, issue: U) -> Self {
+        Self {
+            field: field.into(),
+            issue: issue.into()
+        }
+    }
+
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+    pub fn issue(&self) -> &ValidationIssue {
+        &self.issue
+    }
+}
+
+/// Represents errors that come from Serde JSON, the other configuration formats, IO, or the contents of the configuration itself. 
 #[derive(Debug)]
 pub enum ParsingError {
     Serde(JsonError),
-    IO(std::io::Error)
+    Format(FormatError),
+    IO(std::io::Error),
+    Core(Error),
+    Validation(Vec<ValidationError>)
+}
+impl Display for ParsingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let x: &dyn Display = match self {
+            Self::Serde(x) => x,
+            Self::Format(x) => x,
+            Self::IO(x) => x,
+            Self::Core(x) => x,
+            Self::Validation(errors) => {
+                for (i, error) in errors.iter().enumerate() {
+                    if i != 0 {
+                        write!(f, "; ")?;
+                    }
+                    write!(f, "{error}")?;
+                }
+
+                return Ok(());
+            }
+        };
+
+        x.fmt(f)
+    }
+}
+impl ParsingError {
+    /// The one-based line & column that the error occured at, if known.
+    pub fn position(&self) -> Option<(usize, usize)> {
+        match self {
+            Self::Serde(e) if e.line() != 0 => Some((e.line(), e.column())),
+            Self::Format(e) => e.position(),
+            _ => None
+        }
+    }
 }
 impl From<JsonError> for ParsingError {
     fn from(value: JsonError) -> Self {
         Self::Serde(value)
     }
 }
+impl From<FormatError> for ParsingError {
+    fn from(value: FormatError) -> Self {
+        Self::Format(value)
+    }
+}
 impl From<std::io::Error> for ParsingError {
     fn from(value: std::io::Error) -> Self {
         Self::IO(value)
     }
+}
+impl From<Error> for ParsingError {
+    fn from(value: Error) -> Self {
+        Self::Core(value)
+    }
+}
+impl From<Vec<ValidationError>> for ParsingError {
+    fn from(value: Vec<ValidationError>) -> Self {
+        Self::Validation(value)
+    }
 }
\ No newline at end of file
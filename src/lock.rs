@@ -580,6 +580,18 @@ pub trait OptionRwProvider<T>: RwProvider<Data = Option<T>> {
         *guard = Some(value);
         arc.clear_poison();
     }
+    /// Behaves like `pass`, but returns the value that was previously stored, if any.
+    fn replace(&self, value: T) -> Option<T> {
+        let arc = self.access_raw().take();
+        let mut guard = match arc.write() {
+            Ok(g) => g,
+            Err(e) => e.into_inner()
+        };
+
+        let old = guard.replace(value);
+        arc.clear_poison();
+        old
+    }
     /// If the type `T` implements Default, this will set the data stored internally to the default of `T`. 
     fn set_to_default(&self) where T: Default {
         self.pass(T::default())
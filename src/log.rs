@@ -1,17 +1,28 @@
-use std::fmt::Debug;
-use std::fs::File;
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{Debug, Display};
+use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::path::Path;
-use std::sync::{Arc, Mutex};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::panic::AssertUnwindSafe;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, RwLock, TryLockError};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, Local, Timelike};
 
 use lazy_static::lazy_static;
 use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
 
-use crate::error::{IOError, OperationError};
+use crate::error::{ArgumentValueError, IOError, OperationError};
 use crate::lock::{MutexProvider, OptionMutexProvider, ProtectedAccess};
 
 /// Determines the level used by the logger
-#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Serialize, Deserialize)]
+#[derive(PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum LoggerLevel {
     Debug = 1,
     Info = 2,
@@ -35,260 +46,2479 @@ impl Debug for LoggerLevel {
     }
 }
 
+impl FromStr for LoggerLevel {
+    type Err = ArgumentValueError;
+
+    /// Parses a level by its name, ignoring case. `warn` is accepted for `Warning`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "debug" => Ok(Self::Debug),
+            "info" => Ok(Self::Info),
+            "warning" | "warn" => Ok(Self::Warning),
+            "error" => Ok(Self::Error),
+            "critical" => Ok(Self::Critical),
+            _ => Err(ArgumentValueError::new_display("level", &s))
+        }
+    }
+}
+
+/// Chooses the level of records based on the module that produced them.
+/// The most specific rule matching a record's module is used, and records from modules without a rule use the default level (if any), or the logger's level.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct LevelFilter {
+    default: Option<LoggerLevel>,
+    rules: Vec<(String, LoggerLevel)>
+}
+impl FromStr for LevelFilter {
+    type Err = ArgumentValueError;
+
+    /// Parses a comma separated filter, such as `exdisj::net=warning,myapp::db=debug,info`. Entries without a module set the default level.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut result = Self::default();
+        for entry in s.split(',').map(str::trim).filter(|x| !x.is_empty()) {
+            match entry.split_once('=') {
+                Some((module, level)) => {
+                    let module = module.trim();
+                    if module.is_empty() {
+                        return Err(ArgumentValueError::new_display("filter", &entry));
+                    }
+
+                    result = result.module(module, level.parse()?);
+                },
+                None => result.default = Some(entry.parse()?)
+            }
+        }
+
+        Ok(result)
+    }
+}
+impl LevelFilter {
+    /// A filter without any rules.
+    pub fn new(default: Option<LoggerLevel>) -> Self {
+        Self {
+            default,
+            rules: Vec::new()
+        }
+    }
+    /// Sets the level for `module` and all of its submodules.
+    pub fn module(mut self, module: &str, level: LoggerLevel) -> Self {
+        self.rules.retain(|(x, _)| x != module);
+        self.rules.push((module.to_string(), level));
+        // Longest first, so the most specific rule is found first.
+        self.rules.sort_by_key(|x| std::cmp::Reverse(x.0.len()));
+        self
+    }
+
+    pub fn default_level(&self) -> Option<LoggerLevel> {
+        self.default
+    }
+    pub fn rules(&self) -> &[(String, LoggerLevel)] {
+        &self.rules
+    }
+
+    /// Determines the level for records from `module`, if the filter has a rule (or default) that applies.
+    pub fn level_for(&self, module: Option<&str>) -> Option<LoggerLevel> {
+        if let Some(module) = module {
+            for (prefix, level) in &self.rules {
+                if module == prefix || (module.starts_with(prefix.as_str()) && module[prefix.len()..].starts_with("::")) {
+                    return Some(*level);
+                }
+            }
+        }
+
+        self.default
+    }
+    /// The lowest level that this filter allows for any module.
+    pub fn min_level(&self) -> Option<LoggerLevel> {
+        self.rules.iter().map(|(_, x)| *x).chain(self.default).min()
+    }
+}
+
+/// Determines if the console output of `LoggerRedirect` is colored.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ColorMode {
+    /// Colored when the stream is a terminal, and the `NO_COLOR` environment variable is not set.
+    #[default]
+    Auto,
+    Always,
+    Never
+}
+
+/// The layout of lines written to the console by `LoggerRedirect`.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum ConsoleFormat {
+    /// A compact time stamp, the level (colored, if enabled), the span stack (if any), the message and its fields.
+    #[default]
+    Pretty,
+    /// Only the message.
+    Contents,
+    /// The same layout as the log file.
+    File(LogFormat)
+}
+
 /// Abstraction for the logger to handle writing information to stdout and stderror.
 #[derive(Debug, Clone)]
 pub struct LoggerRedirect {
     std_out: Option<LoggerLevel>,
-    std_err: bool
+    std_err: bool,
+    format: ConsoleFormat,
+    color: ColorMode
 }
 impl Default for LoggerRedirect {
     fn default() -> Self {
-        Self {
-            std_out: None,
-            std_err: true
-        }
+        Self::new(None, true)
     }
 }
 impl LoggerRedirect {
     pub fn new(std_out: Option<LoggerLevel>, std_err: bool) -> Self {
         Self {
             std_out,
-            std_err
+            std_err,
+            format: ConsoleFormat::default(),
+            color: ColorMode::default()
         }
     }
+    pub fn with_format(mut self, format: ConsoleFormat) -> Self {
+        self.format = format;
+        self
+    }
+    pub fn with_color(mut self, color: ColorMode) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn format(&self) -> &ConsoleFormat {
+        &self.format
+    }
+    pub fn color(&self) -> ColorMode {
+        self.color
+    }
 
     pub fn handle_redirect(&self, write: &LoggerWrite) {
+        use std::io::IsTerminal;
+
         if self.std_err && (write.level() == LoggerLevel::Error || write.level() == LoggerLevel::Critical) {
-            eprintln!("{}", write.contents());
+            eprint!("{}", self.render(write, self.use_color(std::io::stderr().is_terminal())));
             return;
         }
 
-        if let Some(s) = self.std_out {
-            if write.level() >= s {
-                println!("{}", write.contents())
+        if let Some(s) = self.std_out && write.level() >= s {
+            print!("{}", self.render(write, self.use_color(std::io::stdout().is_terminal())))
+        }
+    }
+
+    fn use_color(&self, terminal: bool) -> bool {
+        match self.color {
+            ColorMode::Always => true,
+            ColorMode::Never => false,
+            ColorMode::Auto => terminal && std::env::var_os("NO_COLOR").is_none_or(|x| x.is_empty())
+        }
+    }
+
+    /// Produces the line written to the console for this record, including the trailing line break.
+    pub fn render(&self, write: &LoggerWrite, color: bool) -> String {
+        match &self.format {
+            ConsoleFormat::Contents => format!("{}\n", write.contents()),
+            ConsoleFormat::File(format) => write.render(format),
+            ConsoleFormat::Pretty => {
+                // Time stamps made by `LoggerWrite::now` are shortened to the time of day; others are kept as is.
+                let time = DateTime::parse_from_str(write.time_stamp(), "%Y-%m-%d %H:%M:%S%.f %:z")
+                    .map(|x| x.format("%H:%M:%S%.3f").to_string())
+                    .unwrap_or_else(|_| write.time_stamp().to_string());
+                let level = format!("{:<8}", format!("{:?}", write.level()));
+                let level = match color {
+                    true => format!("\x1b[{}m{}\x1b[0m", Self::level_color(write.level()), level),
+                    false => level
+                };
+
+                let mut result = format!("{time} {level} ");
+                if !write.spans().is_empty() {
+                    result += &write.span_path();
+                    result += ": ";
+                }
+                result += write.contents();
+                for (key, value) in write.fields() {
+                    result += &format!(" {key}={value}");
+                }
+
+                result.push('\n');
+                result
+            }
+        }
+    }
+    /// The ANSI color code used for each level.
+    fn level_color(level: LoggerLevel) -> &'static str {
+        match level {
+            LoggerLevel::Debug => "34",
+            LoggerLevel::Info => "32",
+            LoggerLevel::Warning => "33",
+            LoggerLevel::Error => "31",
+            LoggerLevel::Critical => "1;31"
+        }
+    }
+}
+
+/// The layout used when writing records into the log file.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum LogFormat {
+    /// One line per record, with the time stamp, level, span stack (if any) and message, followed by any fields as `key=value`.
+    #[default]
+    Text,
+    /// One JSON object per line, with the keys `time`, `level`, `message`, and `module`, `file`, `line`, `thread`, `thread_id`, `task`, `spans` & `fields` (if present).
+    Json,
+    /// One line per record, laid out by the template.
+    Template(LineTemplate)
+}
+
+/// A value from a record that can be placed into a `LineTemplate`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TemplateField {
+    Time,
+    Level,
+    Module,
+    File,
+    Line,
+    Thread,
+    ThreadId,
+    Task,
+    Message,
+    Fields,
+    Spans
+}
+impl FromStr for TemplateField {
+    type Err = ArgumentValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "time" => Ok(Self::Time),
+            "level" => Ok(Self::Level),
+            "module" => Ok(Self::Module),
+            "file" => Ok(Self::File),
+            "line" => Ok(Self::Line),
+            "thread" => Ok(Self::Thread),
+            "thread_id" => Ok(Self::ThreadId),
+            "task" => Ok(Self::Task),
+            "message" => Ok(Self::Message),
+            "fields" => Ok(Self::Fields),
+            "spans" => Ok(Self::Spans),
+            _ => Err(ArgumentValueError::new_display("placeholder", &s))
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum TemplatePart {
+    Literal(String),
+    Field(TemplateField)
+}
+
+/// The layout of a single log line, such as `{time} {level} [{thread}] {module} ({file}:{line}) {message} {fields}`.
+/// The placeholders are `time`, `level`, `module`, `file`, `line`, `thread`, `thread_id`, `task`, `spans`, `message` and `fields`. Values that a record does not have are written as `-`.
+/// Braces are escaped by doubling them (`{{` and `}}`).
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct LineTemplate {
+    parts: Vec<TemplatePart>
+}
+impl FromStr for LineTemplate {
+    type Err = ArgumentValueError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                },
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                },
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err(ArgumentValueError::new_display("template", &s))
+                        }
+                    }
+
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Field(name.trim().parse()?));
+                },
+                '}' => return Err(ArgumentValueError::new_display("template", &s)),
+                c => literal.push(c)
             }
         }
+
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+
+        Ok(Self { parts })
     }
 }
+impl LineTemplate {
+    /// The fields used by this template, in order.
+    pub fn fields(&self) -> impl Iterator<Item = TemplateField> + '_ {
+        self.parts.iter().filter_map(|x| match x {
+            TemplatePart::Field(f) => Some(*f),
+            TemplatePart::Literal(_) => None
+        })
+    }
+}
+
+/// Converts a value into a field for `LoggerWrite`. Values that cannot be represented are stored as a string describing the error.
+pub fn field_value<T: Serialize + ?Sized>(value: &T) -> Value {
+    serde_json::to_value(value).unwrap_or_else(|e| Value::String(format!("<unrepresentable: {e}>")))
+}
 
 /// A single write-in-progress for the logger
 #[derive(Debug, Clone)]
 pub struct LoggerWrite {
     time_stamp: String,
     contents: String,
-    level: LoggerLevel
+    level: LoggerLevel,
+    module: Option<String>,
+    file: Option<String>,
+    line: Option<u32>,
+    thread: Option<String>,
+    thread_id: Option<u64>,
+    task: Option<String>,
+    spans: Vec<SpanContext>,
+    fields: Vec<(String, Value)>
 }
 impl LoggerWrite {
     pub fn blank(level: LoggerLevel, time_stamp: String) -> Self {
-        Self {
-            time_stamp,
-            contents: String::new(),
-            level
-        }
+        Self::new_str(time_stamp, String::new(), level)
     }
     pub fn new<T: Debug>(time_stamp: String, contents: &T, level: LoggerLevel) -> Self {
-        Self {
-            time_stamp,
-            contents: format!("{:?}", contents),
-            level
-        }
+        Self::new_str(time_stamp, format!("{:?}", contents), level)
     }
     pub fn new_str(time_stamp: String, contents: String, level: LoggerLevel) -> Self {
         Self {
             time_stamp,
             contents,
-            level
+            level,
+            module: None,
+            file: None,
+            line: None,
+            thread: None,
+            thread_id: None,
+            task: None,
+            spans: Vec::new(),
+            fields: Vec::new()
         }
     }
+    /// Creates a record with the current time as its time stamp, the current thread (and task, if inside of one), and the spans entered on this thread.
+    pub fn now(level: LoggerLevel, contents: String) -> Self {
+        let mut result = Self::new_str(format!("{}", chrono::Local::now()), contents, level);
+
+        let thread = std::thread::current();
+        result.thread = thread.name().map(str::to_string);
+        // `ThreadId::as_u64` is unstable, so the number is taken from the `Debug` output (`ThreadId(N)`).
+        result.thread_id = format!("{:?}", thread.id())
+            .trim_start_matches("ThreadId(")
+            .trim_end_matches(')')
+            .parse()
+            .ok();
+
+        #[cfg(feature="async")]
+        {
+            result.task = tokio::task::try_id().map(|x| x.to_string());
+        }
+
+        result.spans = current_spans();
+
+        result
+    }
 
     pub fn ignore(&self, level: LoggerLevel) -> bool {
         self.level < level
     }
 
+    pub fn time_stamp(&self) -> &str {
+        &self.time_stamp
+    }
     pub fn contents(&self) -> &str {
         &self.contents
     }
     pub fn level(&self) -> LoggerLevel {
         self.level
     }
+    /// The module that produced the record, if known.
+    pub fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+    pub fn fields(&self) -> &[(String, Value)] {
+        &self.fields
+    }
+    /// The source file that produced the record, if known.
+    pub fn file(&self) -> Option<&str> {
+        self.file.as_deref()
+    }
+    /// The line in the source file that produced the record, if known.
+    pub fn line(&self) -> Option<u32> {
+        self.line
+    }
+    /// The name of the thread that produced the record, if it has one.
+    pub fn thread(&self) -> Option<&str> {
+        self.thread.as_deref()
+    }
+    pub fn thread_id(&self) -> Option<u64> {
+        self.thread_id
+    }
+    /// The id of the tokio task that produced the record, if it was inside of one.
+    pub fn task(&self) -> Option<&str> {
+        self.task.as_deref()
+    }
+    /// The spans that the record was produced inside of, from outermost to innermost.
+    pub fn spans(&self) -> &[SpanContext] {
+        &self.spans
+    }
+    /// Sets the module that produced the record.
+    pub fn with_module(mut self, module: &str) -> Self {
+        self.module = Some(module.to_string());
+        self
+    }
+    /// Sets the source file & line that produced the record.
+    pub fn with_location(mut self, file: &str, line: u32) -> Self {
+        self.file = Some(file.to_string());
+        self.line = Some(line);
+        self
+    }
     pub fn append<T: Debug>(&mut self, cont: &T) {
         let new_cont: String = format!("{:?}", cont);
         self.contents += &new_cont;
     }
+    /// Appends `cont` to the contents, using its `Display` formatting.
+    pub fn push<T: Display + ?Sized>(&mut self, cont: &T) {
+        use std::fmt::Write;
+        let _ = write!(self.contents, "{}", cont);
+    }
+    /// Attaches a key-value field to the record.
+    pub fn push_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) {
+        self.fields.push((key.to_string(), field_value(value)));
+    }
+
+    /// Produces the line written to the log file for this record, including the trailing line break.
+    pub fn render(&self, format: &LogFormat) -> String {
+        match format {
+            LogFormat::Text => {
+                let mut result = match self.spans.is_empty() {
+                    true => format!("{} {:?} {}", self.time_stamp, self.level, self.contents),
+                    false => format!("{} {:?} {}: {}", self.time_stamp, self.level, self.span_path(), self.contents)
+                };
+                for (key, value) in &self.fields {
+                    result += &format!(" {key}={value}");
+                }
+
+                result.push('\n');
+                result
+            },
+            LogFormat::Json => {
+                let mut map = Map::new();
+                map.insert("time".to_string(), Value::String(self.time_stamp.clone()));
+                map.insert("level".to_string(), Value::String(format!("{:?}", self.level)));
+                map.insert("message".to_string(), Value::String(self.contents.clone()));
+                let optional = [
+                    ("module", self.module.clone().map(Value::String)),
+                    ("file", self.file.clone().map(Value::String)),
+                    ("line", self.line.map(Value::from)),
+                    ("thread", self.thread.clone().map(Value::String)),
+                    ("thread_id", self.thread_id.map(Value::from)),
+                    ("task", self.task.clone().map(Value::String))
+                ];
+                for (key, value) in optional {
+                    if let Some(value) = value {
+                        map.insert(key.to_string(), value);
+                    }
+                }
+                if !self.spans.is_empty() {
+                    map.insert("spans".to_string(), Value::Array(self.spans.iter().map(SpanContext::to_value).collect()));
+                }
+                if !self.fields.is_empty() {
+                    map.insert("fields".to_string(), Value::Object(self.fields.iter().cloned().collect()));
+                }
+
+                let mut result = Value::Object(map).to_string();
+                result.push('\n');
+                result
+            },
+            LogFormat::Template(template) => {
+                let mut result = String::new();
+                for part in &template.parts {
+                    match part {
+                        TemplatePart::Literal(x) => result += x,
+                        TemplatePart::Field(x) => result += &self.template_value(*x)
+                    }
+                }
+
+                result.push('\n');
+                result
+            }
+        }
+    }
+    /// The span stack, as `outer{key=value}:inner`.
+    fn span_path(&self) -> String {
+        self.spans.iter().map(|x| x.to_string()).collect::<Vec<_>>().join(":")
+    }
+    fn template_value(&self, field: TemplateField) -> String {
+        let or_missing = |x: Option<String>| x.unwrap_or_else(|| "-".to_string());
+        match field {
+            TemplateField::Time => self.time_stamp.clone(),
+            TemplateField::Level => format!("{:?}", self.level),
+            TemplateField::Module => or_missing(self.module.clone()),
+            TemplateField::File => or_missing(self.file.clone()),
+            TemplateField::Line => or_missing(self.line.map(|x| x.to_string())),
+            TemplateField::Thread => or_missing(self.thread.clone().or_else(|| self.thread_id.map(|x| x.to_string()))),
+            TemplateField::ThreadId => or_missing(self.thread_id.map(|x| x.to_string())),
+            TemplateField::Task => or_missing(self.task.clone()),
+            TemplateField::Message => self.contents.clone(),
+            TemplateField::Fields => self.fields
+                .iter()
+                .map(|(key, value)| format!("{key}={value}"))
+                .collect::<Vec<_>>()
+                .join(" "),
+            TemplateField::Spans => match self.spans.is_empty() {
+                true => "-".to_string(),
+                false => self.span_path()
+            }
+        }
+    }
 }
 impl From<LoggerWrite> for Vec<u8> {
     fn from(value: LoggerWrite) -> Self {
-        format!("{} {:?} {}\n", value.time_stamp, value.level, value.contents).into_bytes()
+        value.render(&LogFormat::Text).into_bytes()
     }
 }
 
-/// A structure that facilitates the writing done.
+/// How often a log file is rotated, regardless of its size.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum RotationPeriod {
+    Hourly,
+    Daily
+}
+impl RotationPeriod {
+    /// Determines if `a` and `b` fall within different periods.
+    fn crossed(&self, a: &DateTime<Local>, b: &DateTime<Local>) -> bool {
+        match self {
+            Self::Hourly => a.date_naive() != b.date_naive() || a.hour() != b.hour(),
+            Self::Daily => a.date_naive() != b.date_naive()
+        }
+    }
+}
+
+/// Determines when a log file is rotated, and how many rotated files are kept. 
+/// Rotated files are named `<file>.1` (the most recent) through `<file>.<keep>`, with `.gz` appended when compressed.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct RotationPolicy {
+    max_bytes: Option<u64>,
+    period: Option<RotationPeriod>,
+    keep: usize,
+    compress: bool
+}
+impl RotationPolicy {
+    /// Creates a policy that keeps the last `keep` rotated files. By itself, this policy never rotates, so `max_bytes` and/or `period` should be set.
+    pub fn new(keep: usize) -> Self {
+        Self {
+            max_bytes: None,
+            period: None,
+            keep,
+            compress: false
+        }
+    }
+    /// Rotates the file before a write would make it larger than `bytes`.
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+    /// Rotates the file when the first write in a new hour/day happens.
+    pub fn period(mut self, period: RotationPeriod) -> Self {
+        self.period = Some(period);
+        self
+    }
+    /// Compresses rotated files with gzip.
+    #[cfg(feature="gzip")]
+    pub fn compress(mut self, compress: bool) -> Self {
+        self.compress = compress;
+        self
+    }
+
+    pub fn keep(&self) -> usize {
+        self.keep
+    }
+}
+
+/// The file written to by the logger, which can be rotated if it was opened from a path.
 #[derive(Debug)]
-pub struct LoadedLogger {
+pub struct LogFile {
     file: File,
-    level: LoggerLevel,
-    redirect: LoggerRedirect,
-    write: Option<LoggerWrite>
+    path: Option<PathBuf>,
+    size: u64,
+    opened: DateTime<Local>,
+    rotation: Option<RotationPolicy>
 }
-impl LoadedLogger {
-    /// Initalizes the structure.
-    pub fn new(file: File, level: LoggerLevel, redirect: LoggerRedirect) -> Self {
+impl From<File> for LogFile {
+    fn from(file: File) -> Self {
+        let size = file.metadata().map(|x| x.len()).unwrap_or(0);
         Self {
             file,
-            level,
-            redirect,
-            write: None
+            path: None,
+            size,
+            opened: Local::now(),
+            rotation: None
         }
     }
+}
+impl LogFile {
+    /// Opens the file at `path`. If `append` is set, new records are added to the end of the existing file, otherwise the file is truncated.
+    pub fn open<P: AsRef<Path>>(path: P, append: bool) -> Result<Self, std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(&path)?;
 
-    /// Determines the level at which the logger is operating.
-    pub fn level(&self) -> LoggerLevel {
-        self.level
+        let metadata = file.metadata()?;
+        // When appending, the file may have been started in a previous rotation period.
+        let opened = match metadata.modified() {
+            Ok(v) if append && metadata.len() != 0 => DateTime::<Local>::from(v),
+            _ => Local::now()
+        };
+
+        Ok(Self {
+            file,
+            path: Some(path.as_ref().to_path_buf()),
+            size: metadata.len(),
+            opened,
+            rotation: None
+        })
     }
-    /// Determines the logger's redirect.
-    pub fn redirect(&self) -> &LoggerRedirect {
-        &self.redirect
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
     }
-    /// Sets the logger's redirect.
-    pub fn set_redirect(&mut self, new: LoggerRedirect) {
-        self.redirect = new
+    /// The current size of the file, in bytes.
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+    pub fn rotation(&self) -> Option<&RotationPolicy> {
+        self.rotation.as_ref()
     }
+    /// Sets the rotation policy. This has no effect if the file was not opened from a path.
+    pub fn set_rotation(&mut self, rotation: Option<RotationPolicy>) {
+        self.rotation = rotation;
+    }
+    /// Closes and reopens the file at its path, keeping the rotation policy. This is used after the file has been moved by an external tool, such as logrotate.
+    pub fn reopen(&mut self) -> Result<(), std::io::Error> {
+        let path = self.path.clone().ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Unsupported, "the log file was not opened from a path"))?;
 
-    /// Determines if the logger is currently writing a log value.
-    pub fn is_writing(&self) -> bool {
-        self.write.is_some()
+        let mut new = Self::open(path, true)?;
+        new.rotation = self.rotation.take();
+        *self = new;
+        Ok(())
     }
-    /// Determines the level at which the current log is being written in.
-    pub fn writing_level(&self) -> Option<LoggerLevel> {
-        let write = self.write.as_ref()?;
-        Some(write.level())
+
+    /// Writes all of `bytes` to the file, rotating it beforehand if the policy requires it.
+    pub fn write_all(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        if self.should_rotate(bytes.len() as u64) {
+            self.rotate()?;
+        }
+
+        self.file.write_all(bytes)?;
+        self.size += bytes.len() as u64;
+        Ok(())
     }
-    /// Determines if the current log, if being written, will actually be written to the file.
-    pub fn current_log_ignored(&self) -> Option<bool> {
-        let write = self.write.as_ref()?;
-        Some( write.level() < self.level )
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()
     }
 
-    /// Prepares the logger for a new writing session.
-    /// Fails if there is currently a log ongoing.
-    pub fn start_log(&mut self, level: LoggerLevel) -> Result<(), OperationError>{
-        if self.is_writing() {
-            return Err( OperationError::new("start log", format!("log already started at level {:?}", self.writing_level().unwrap())) );
+    fn should_rotate(&self, incoming: u64) -> bool {
+        let Some(policy) = self.rotation.as_ref() else { return false };
+        if self.path.is_none() || self.size == 0 {
+            return false;
         }
 
-        let write = LoggerWrite::new_str(
-            format!("{}", chrono::Local::now()),
-            String::new(),
-            level
-        );
+        let too_large = policy.max_bytes.map(|x| self.size + incoming > x).unwrap_or(false);
+        let expired = policy.period.map(|x| x.crossed(&self.opened, &Local::now())).unwrap_or(false);
+        too_large || expired
+    }
+    /// Moves the current file to `<file>.1`, shifting older files up and removing any past the number to keep, and then starts a new, empty file.
+    pub fn rotate(&mut self) -> Result<(), std::io::Error> {
+        let (Some(path), Some(policy)) = (self.path.as_ref(), self.rotation.as_ref()) else {
+            return Ok(());
+        };
+
+        self.file.flush()?;
+        let suffix = if policy.compress { ".gz" } else { "" };
+        let rotated = |i: usize| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(format!(".{i}{suffix}"));
+            PathBuf::from(name)
+        };
+
+        if policy.keep == 0 {
+            std::fs::remove_file(path)?;
+        }
+        else {
+            let _ = std::fs::remove_file(rotated(policy.keep));
+            for i in (1..policy.keep).rev() {
+                let from = rotated(i);
+                if from.exists() {
+                    std::fs::rename(&from, rotated(i + 1))?;
+                }
+            }
+
+            if policy.compress {
+                compress_file(path, &rotated(1))?;
+                std::fs::remove_file(path)?;
+            }
+            else {
+                std::fs::rename(path, rotated(1))?;
+            }
+        }
 
-        self.write = Some(write);
+        self.file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        self.size = 0;
+        self.opened = Local::now();
         Ok(())
     }
-    /// Writes data into the current log, if one is active.
-    pub fn write<T: Debug>(&mut self, obj: &T) -> bool {
-        let write = match self.write.as_mut() {
-            Some(s) => s,
-            None => return false
-        };
+}
 
-        write.append(obj);
-        true
+#[cfg(feature="gzip")]
+fn compress_file(from: &Path, to: &Path) -> Result<(), std::io::Error> {
+    let mut input = File::open(from)?;
+    let mut encoder = flate2::write::GzEncoder::new(File::create(to)?, flate2::Compression::default());
+    std::io::copy(&mut input, &mut encoder)?;
+    encoder.finish()?.sync_all()
+}
+#[cfg(not(feature="gzip"))]
+fn compress_file(_: &Path, _: &Path) -> Result<(), std::io::Error> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "compression requires the gzip feature"))
+}
+
+/// A destination that the logger fans records out to. Each sink has its own minimum level, and is only given records at or above it.
+pub trait LogSink: Send + Debug {
+    /// The minimum level of records written to this sink.
+    fn level(&self) -> LoggerLevel;
+    /// Writes a single record. If this fails, the sink is removed from the logger.
+    fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error>;
+    /// Flushes any buffered records.
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        Ok(())
     }
-    /// Flushes the internal buffer of the log, and marks the logger as being completed.
-    pub fn end_log(&mut self) -> Result<(), IOError> {
-        let write = self.write.as_ref().ok_or(IOError::Core( OperationError::new("end log", "no log was started").into() ))?;
+}
+
+/// A sink that writes into a log file, which may be rotated.
+#[derive(Debug)]
+pub struct FileSink {
+    file: LogFile,
+    level: LoggerLevel,
+    format: LogFormat
+}
+impl LogSink for FileSink {
+    fn level(&self) -> LoggerLevel {
+        self.level
+    }
+    fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        self.file.write_all(write.render(&self.format).as_bytes())
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.file.flush()
+    }
+}
+impl FileSink {
+    pub fn new(file: LogFile, level: LoggerLevel) -> Self {
+        Self {
+            file,
+            level,
+            format: LogFormat::default()
+        }
+    }
+    /// Opens the file at `path`, keeping its existing contents.
+    pub fn open<P: AsRef<Path>>(path: P, level: LoggerLevel) -> Result<Self, std::io::Error> {
+        Ok(Self::new(LogFile::open(path, true)?, level))
+    }
+    /// Opens the file at `path`, keeping its existing contents, and rotates it according to `rotation`.
+    pub fn open_rotating<P: AsRef<Path>>(path: P, level: LoggerLevel, rotation: RotationPolicy) -> Result<Self, std::io::Error> {
+        let mut file = LogFile::open(path, true)?;
+        file.set_rotation(Some(rotation));
+
+        Ok(Self::new(file, level))
+    }
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+
+/// The standard stream that a `ConsoleSink` writes to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConsoleStream {
+    StdOut,
+    StdErr
+}
 
-        if !write.ignore(self.level) {
-            let mut contents = write.time_stamp.clone() + write.contents();
-            contents.push('\n');
+/// A sink that writes every record to stdout or stderr.
+#[derive(Debug)]
+pub struct ConsoleSink {
+    stream: ConsoleStream,
+    level: LoggerLevel,
+    format: LogFormat
+}
+impl LogSink for ConsoleSink {
+    fn level(&self) -> LoggerLevel {
+        self.level
+    }
+    fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        let line = write.render(&self.format);
+        match self.stream {
+            ConsoleStream::StdOut => std::io::stdout().lock().write_all(line.as_bytes()),
+            ConsoleStream::StdErr => std::io::stderr().lock().write_all(line.as_bytes())
+        }
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        match self.stream {
+            ConsoleStream::StdOut => std::io::stdout().flush(),
+            ConsoleStream::StdErr => std::io::stderr().flush()
+        }
+    }
+}
+impl ConsoleSink {
+    pub fn new(stream: ConsoleStream, level: LoggerLevel) -> Self {
+        Self {
+            stream,
+            level,
+            format: LogFormat::default()
+        }
+    }
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
 
-            self.redirect.handle_redirect(write);
+/// A sink that keeps the most recent records in memory, discarding the oldest once it is full. Clones of this sink share the same buffer, so a clone can be kept to read the records.
+#[derive(Debug, Clone)]
+pub struct MemorySink {
+    records: Arc<Mutex<VecDeque<LoggerWrite>>>,
+    capacity: usize,
+    level: LoggerLevel
+}
+impl LogSink for MemorySink {
+    fn level(&self) -> LoggerLevel {
+        self.level
+    }
+    fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        if self.capacity == 0 {
+            return Ok(());
+        }
 
-            self.file.write(contents.as_bytes()).map_err(IOError::from)?;
+        let mut records = match self.records.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+        if records.len() == self.capacity {
+            records.pop_front();
         }
+        records.push_back(write.clone());
+        Ok(())
+    }
+}
+impl MemorySink {
+    /// Creates a sink that keeps, at most, `capacity` records.
+    pub fn new(level: LoggerLevel, capacity: usize) -> Self {
+        Self {
+            records: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+            level
+        }
+    }
+
+    /// A copy of the records currently stored, from oldest to newest.
+    pub fn records(&self) -> Vec<LoggerWrite> {
+        match self.records.lock() {
+            Ok(v) => v.iter().cloned().collect(),
+            Err(e) => e.into_inner().iter().cloned().collect()
+        }
+    }
+    /// Removes all records currently stored.
+    pub fn clear(&self) {
+        match self.records.lock() {
+            Ok(mut v) => v.clear(),
+            Err(e) => e.into_inner().clear()
+        }
+    }
+}
+
+/// A sink that writes records to a stream, such as a TCP or Unix-domain socket. The JSON format is usually the most useful for these.
+#[derive(Debug)]
+pub struct StreamSink<W> where W: Write + Send + Debug {
+    stream: W,
+    level: LoggerLevel,
+    format: LogFormat
+}
+impl<W> LogSink for StreamSink<W> where W: Write + Send + Debug {
+    fn level(&self) -> LoggerLevel {
+        self.level
+    }
+    fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        self.stream.write_all(write.render(&self.format).as_bytes())
+    }
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.stream.flush()
+    }
+}
+impl<W> StreamSink<W> where W: Write + Send + Debug {
+    pub fn new(stream: W, level: LoggerLevel) -> Self {
+        Self {
+            stream,
+            level,
+            format: LogFormat::default()
+        }
+    }
+    pub fn with_format(mut self, format: LogFormat) -> Self {
+        self.format = format;
+        self
+    }
+}
+impl StreamSink<TcpStream> {
+    /// Connects to a TCP listener at `addr`.
+    pub fn tcp<A: ToSocketAddrs>(addr: A, level: LoggerLevel) -> Result<Self, std::io::Error> {
+        Ok(Self::new(TcpStream::connect(addr)?, level))
+    }
+}
+#[cfg(unix)]
+impl StreamSink<std::os::unix::net::UnixStream> {
+    /// Connects to a Unix-domain socket at `path`.
+    pub fn unix<P: AsRef<Path>>(path: P, level: LoggerLevel) -> Result<Self, std::io::Error> {
+        Ok(Self::new(std::os::unix::net::UnixStream::connect(path)?, level))
+    }
+}
+
+/// Limits how often records of a level are written.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct RateLimit {
+    burst: Option<(u32, Duration)>,
+    dedup: bool
+}
+impl RateLimit {
+    /// Allows, at most, `burst` records from each call site per `window`. The number of records suppressed is reported once the window ends.
+    ///
+    /// In the background writing mode, the report is written shortly after the window ends. Otherwise, it is written with the next record, or by `Logger::report_suppressed` & `Logger::flush`.
+    pub fn new(burst: u32, window: Duration) -> Self {
+        Self {
+            burst: Some((burst, window)),
+            dedup: false
+        }
+    }
+    /// Only collapses repeated messages, without a limit on the rate.
+    pub fn dedup_only() -> Self {
+        Self {
+            burst: None,
+            dedup: true
+        }
+    }
+    /// Collapses consecutive identical messages from the same call site into a single "last message repeated N times" record.
+    /// The count is reported when a different message arrives, or once the message stops repeating (see `RateLimit::new` for when that is written).
+    pub fn dedup(mut self, dedup: bool) -> Self {
+        self.dedup = dedup;
+        self
+    }
+}
+
+#[derive(Debug)]
+struct CallsiteState {
+    start: Instant,
+    count: u32,
+    suppressed: u64,
+    level: LoggerLevel,
+    module: Option<String>
+}
+
+#[derive(Debug)]
+struct LastMessage {
+    callsite: String,
+    contents: String,
+    level: LoggerLevel,
+    module: Option<String>,
+    repeated: u64,
+    last_seen: Instant
+}
+
+/// Suppresses records according to a `RateLimit` for each level. Call sites are identified by the file & line of the record, or by its module & contents if the location is unknown.
+#[derive(Debug, Default)]
+pub struct RateLimiter {
+    limits: HashMap<LoggerLevel, RateLimit>,
+    callsites: HashMap<String, CallsiteState>,
+    last: Option<LastMessage>
+}
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Sets the limit for records at `level`.
+    pub fn level(mut self, level: LoggerLevel, limit: RateLimit) -> Self {
+        self.limits.insert(level, limit);
+        self
+    }
+    /// Sets the same limit for records at every level.
+    pub fn all(mut self, limit: RateLimit) -> Self {
+        for level in [LoggerLevel::Debug, LoggerLevel::Info, LoggerLevel::Warning, LoggerLevel::Error, LoggerLevel::Critical] {
+            self.limits.insert(level, limit);
+        }
+        self
+    }
+
+    fn callsite(write: &LoggerWrite) -> String {
+        match (write.file(), write.line()) {
+            (Some(file), Some(line)) => format!("{file}:{line}"),
+            _ => format!("{}: {}", write.module().unwrap_or("-"), write.contents())
+        }
+    }
+    fn summary(level: LoggerLevel, module: Option<&str>, contents: String) -> LoggerWrite {
+        let write = LoggerWrite::new_str(format!("{}", Local::now()), contents, level);
+        match module {
+            Some(module) => write.with_module(module),
+            None => write
+        }
+    }
+
+    /// Determines if `write` should be written, along with any summaries of suppressed records that should be written before it.
+    fn admit(&mut self, write: &LoggerWrite, now: Instant) -> (bool, Vec<LoggerWrite>) {
+        let callsite = Self::callsite(write);
+        let limit = self.limits.get(&write.level()).copied();
+        let mut summaries = self.expired(now);
+
+        if let Some(last) = self.last.as_mut() {
+            if last.callsite == callsite && last.contents == write.contents() && last.level == write.level() {
+                last.repeated += 1;
+                last.last_seen = now;
+                return (false, summaries);
+            }
+
+            summaries.extend(self.end_repeat());
+        }
+        if limit.is_some_and(|x| x.dedup) {
+            self.last = Some(LastMessage {
+                callsite: callsite.clone(),
+                contents: write.contents().to_string(),
+                level: write.level(),
+                module: write.module().map(str::to_string),
+                repeated: 0,
+                last_seen: now
+            });
+        }
+
+        let Some((burst, _)) = limit.and_then(|x| x.burst) else {
+            return (true, summaries);
+        };
+
+        let state = self.callsites.entry(callsite).or_insert_with(|| CallsiteState {
+            start: now,
+            count: 0,
+            suppressed: 0,
+            level: write.level(),
+            module: write.module().map(str::to_string)
+        });
+        state.count += 1;
+        if state.count > burst {
+            state.suppressed += 1;
+            // A suppressed record must not be reported as repeated, as it was never written.
+            self.last = None;
+            return (false, summaries);
+        }
+
+        (true, summaries)
+    }
+    /// Reports the call sites whose window has ended, and forgets the ones without suppressed records.
+    /// A run of repeated messages is also reported once it has been idle for its level's window, or `REPORT_INTERVAL` if there is none.
+    fn expired(&mut self, now: Instant) -> Vec<LoggerWrite> {
+        let idle = self.last.as_ref().is_some_and(|last| {
+            let window = self.limits.get(&last.level).and_then(|x| x.burst).map(|x| x.1).unwrap_or(REPORT_INTERVAL);
+            now.duration_since(last.last_seen) >= window
+        });
+        let mut result = match idle {
+            true => self.end_repeat().into_iter().collect(),
+            false => Vec::new()
+        };
+
+        let limits = &self.limits;
+        self.callsites.retain(|callsite, state| {
+            let window = limits.get(&state.level).and_then(|x| x.burst).map(|x| x.1).unwrap_or_default();
+            if now.duration_since(state.start) < window {
+                return true;
+            }
+
+            if state.suppressed != 0 {
+                result.push(Self::summary(state.level, state.module.as_deref(), format!("suppressed {} messages from {}", state.suppressed, callsite)));
+            }
+            false
+        });
+
+        result
+    }
+    fn end_repeat(&mut self) -> Option<LoggerWrite> {
+        let last = self.last.take()?;
+        match last.repeated {
+            0 => None,
+            n => Some(Self::summary(last.level, last.module.as_deref(), format!("last message repeated {} times", n)))
+        }
+    }
+    /// Reports every suppressed & repeated record, regardless of whether its window has ended.
+    fn drain(&mut self) -> Vec<LoggerWrite> {
+        let mut result: Vec<LoggerWrite> = self.end_repeat().into_iter().collect();
+        for (callsite, state) in self.callsites.drain() {
+            if state.suppressed != 0 {
+                result.push(Self::summary(state.level, state.module.as_deref(), format!("suppressed {} messages from {}", state.suppressed, callsite)));
+            }
+        }
+
+        result
+    }
+}
+
+/// A structure that facilitates the writing done.
+#[derive(Debug)]
+pub struct LoadedLogger {
+    file: Option<LogFile>,
+    level: LoggerLevel,
+    redirect: LoggerRedirect,
+    format: LogFormat,
+    sinks: Vec<Box<dyn LogSink>>,
+    filter: Option<LevelFilter>,
+    limiter: Option<RateLimiter>,
+    write: Option<LoggerWrite>
+}
+impl LoadedLogger {
+    /// Initalizes the structure.
+    pub fn new(file: File, level: LoggerLevel, redirect: LoggerRedirect) -> Self {
+        Self::with_file(file.into(), level, redirect)
+    }
+    /// Initalizes the structure with a file that may be rotated.
+    pub fn with_file(file: LogFile, level: LoggerLevel, redirect: LoggerRedirect) -> Self {
+        Self {
+            file: Some(file),
+            level,
+            redirect,
+            format: LogFormat::default(),
+            sinks: Vec::new(),
+            filter: None,
+            limiter: None,
+            write: None
+        }
+    }
+    /// Initalizes the structure without a main file or redirect, so that records are only written to `sinks`.
+    pub fn from_sinks(sinks: Vec<Box<dyn LogSink>>) -> Self {
+        Self {
+            file: None,
+            level: sinks.iter().map(|x| x.level()).min().unwrap_or(LoggerLevel::Critical),
+            redirect: LoggerRedirect::new(None, false),
+            format: LogFormat::default(),
+            sinks,
+            filter: None,
+            limiter: None,
+            write: None
+        }
+    }
+
+    /// Determines the level at which the logger's main file and redirect are operating.
+    pub fn level(&self) -> LoggerLevel {
+        self.level
+    }
+    /// Determines the lowest level that any output of the logger (main file, redirect, or sink) accepts.
+    pub fn min_level(&self) -> LoggerLevel {
+        self.sinks
+            .iter()
+            .map(|x| x.level())
+            .chain(self.filter.as_ref().and_then(|x| x.min_level()))
+            .fold(self.level, std::cmp::min)
+    }
+    /// The per-module filter, if any.
+    pub fn filter(&self) -> Option<&LevelFilter> {
+        self.filter.as_ref()
+    }
+    /// Sets the rate limiter, replacing the existing one. Records suppressed by the existing limiter are reported first.
+    pub fn set_rate_limiter(&mut self, limiter: Option<RateLimiter>) -> Result<(), std::io::Error> {
+        if let Some(mut old) = std::mem::replace(&mut self.limiter, limiter) {
+            for summary in old.drain() {
+                self.write_outputs(&summary)?;
+            }
+        }
+
+        Ok(())
+    }
+    /// Sets the per-module filter. The level it chooses for a record's module replaces the level of the main file & redirect, and records below it are not given to any sink.
+    pub fn set_filter(&mut self, filter: Option<LevelFilter>) {
+        self.filter = filter
+    }
+    /// Adds a sink, which will receive every record at or above its own level.
+    pub fn add_sink<S: LogSink + 'static>(&mut self, sink: S) {
+        self.sinks.push(Box::new(sink))
+    }
+    /// The number of sinks that the logger writes to, not including the main file.
+    pub fn sink_count(&self) -> usize {
+        self.sinks.len()
+    }
+    /// Determines the logger's redirect.
+    pub fn redirect(&self) -> &LoggerRedirect {
+        &self.redirect
+    }
+    /// Sets the level at which the logger's main file and redirect operate.
+    pub fn set_level(&mut self, level: LoggerLevel) {
+        self.level = level
+    }
+    /// Sets the logger's redirect.
+    pub fn set_redirect(&mut self, new: LoggerRedirect) {
+        self.redirect = new
+    }
+    /// Determines the layout used for records in the log file.
+    pub fn format(&self) -> &LogFormat {
+        &self.format
+    }
+    /// Sets the layout used for records in the log file.
+    pub fn set_format(&mut self, format: LogFormat) {
+        self.format = format
+    }
+    /// The main file that the logger writes to, if it has one.
+    pub fn file(&self) -> Option<&LogFile> {
+        self.file.as_ref()
+    }
+    /// Switches the main file to the one at `path`, keeping the rotation policy. The new file is opened before the old one is closed, so on failure the logger is unchanged.
+    pub fn set_path<P: AsRef<Path>>(&mut self, path: P, append: bool) -> Result<(), std::io::Error> {
+        let mut file = LogFile::open(path, append)?;
+        if let Some(old) = self.file.as_mut() {
+            let _ = old.flush();
+            file.set_rotation(old.rotation.take());
+        }
+
+        self.file = Some(file);
+        Ok(())
+    }
+    /// Closes and reopens the main file at its path. This does nothing if there is no main file.
+    pub fn reopen(&mut self) -> Result<(), std::io::Error> {
+        match self.file.as_mut() {
+            Some(file) => file.reopen(),
+            None => Ok(())
+        }
+    }
+    /// Sets the rotation policy of the logger's main file.
+    pub fn set_rotation(&mut self, rotation: Option<RotationPolicy>) {
+        if let Some(file) = self.file.as_mut() {
+            file.set_rotation(rotation)
+        }
+    }
+
+    /// Determines if the logger is currently writing a log value.
+    pub fn is_writing(&self) -> bool {
+        self.write.is_some()
+    }
+    /// Determines the level at which the current log is being written in.
+    pub fn writing_level(&self) -> Option<LoggerLevel> {
+        let write = self.write.as_ref()?;
+        Some(write.level())
+    }
+    /// Determines if the current log, if being written, will actually be written to the file.
+    pub fn current_log_ignored(&self) -> Option<bool> {
+        let write = self.write.as_ref()?;
+        Some( write.level() < self.level )
+    }
+
+    /// Prepares the logger for a new writing session.
+    /// Fails if there is currently a log ongoing.
+    pub fn start_log(&mut self, level: LoggerLevel) -> Result<(), OperationError>{
+        if self.is_writing() {
+            return Err( OperationError::new("start log", format!("log already started at level {:?}", self.writing_level().unwrap())) );
+        }
+
+        self.write = Some(LoggerWrite::now(level, String::new()));
+        Ok(())
+    }
+    /// Writes data into the current log, if one is active.
+    pub fn write<T: Display + ?Sized>(&mut self, obj: &T) -> bool {
+        let write = match self.write.as_mut() {
+            Some(s) => s,
+            None => return false
+        };
+
+        write.push(obj);
+        true
+    }
+    /// Writes the current log in the same way as `write_record`, and marks the logger as being completed.
+    pub fn end_log(&mut self) -> Result<(), IOError> {
+        let write = self.write.take().ok_or(IOError::Core( OperationError::new("end log", "no log was started").into() ))?;
+
+        self.write_record(&write).map_err(IOError::from)
+    }
+
+    /// Regardless of a log being currently in progress or not, this will direclty write a string into the log file. 
+    pub fn write_direct(&mut self, contents: String, level: LoggerLevel) -> Result<(), std::io::Error> {
+        self.write_record(&LoggerWrite::now(level, contents))
+    }
+    /// Regardless of a log being currently in progress or not, this will directly write a complete record into the log file & sinks. 
+    /// The record is only given to the outputs whose level it meets. If a sink fails, it is removed, and the error is reported to stderr.
+    /// Errors from the main file are returned.
+    /// If a rate limiter is set, the record may be suppressed, and summaries of suppressed records may be written before it.
+    pub fn write_record(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        let filtered = self.filter.as_ref().and_then(|x| x.level_for(write.module()));
+        if let Some(level) = filtered && write.level() < level {
+            return Ok(());
+        }
+
+        if let Some(limiter) = self.limiter.as_mut() {
+            let (admit, summaries) = limiter.admit(write, Instant::now());
+            for summary in summaries {
+                self.write_outputs(&summary)?;
+            }
+
+            if !admit {
+                return Ok(());
+            }
+        }
+
+        self.write_outputs(write)
+    }
+    /// Writes the record into the outputs whose level it meets.
+    fn write_outputs(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+        let filtered = self.filter.as_ref().and_then(|x| x.level_for(write.module()));
+        if let Some(level) = filtered && write.level() < level {
+            return Ok(());
+        }
+
+        self.sinks.retain_mut(|sink| {
+            if write.level() < sink.level() {
+                return true;
+            }
+
+            match sink.write(write) {
+                Ok(_) => true,
+                Err(e) => {
+                    eprintln!("unable to write to log sink '{:?}' because of '{:?}'. The sink will be removed", sink, e);
+                    false
+                }
+            }
+        });
+
+        if write.level() < filtered.unwrap_or(self.level) {
+            return Ok(());
+        }
+
+        self.redirect.handle_redirect(write);
+        match self.file.as_mut() {
+            Some(file) => file.write_all(write.render(&self.format).as_bytes()),
+            None => Ok(())
+        }
+    }
+    /// Reports the records suppressed by the rate limiter in windows that have already ended.
+    /// This is done automatically when a record is written, but should be called periodically if the logger may go quiet for a while.
+    pub fn report_suppressed(&mut self) -> Result<(), std::io::Error> {
+        let summaries = self.limiter.as_mut().map(|x| x.expired(Instant::now())).unwrap_or_default();
+        for summary in summaries {
+            self.write_outputs(&summary)?;
+        }
+
+        Ok(())
+    }
+    /// Reports all records suppressed by the rate limiter, and flushes the main file and every sink.
+    pub fn flush(&mut self) -> Result<(), std::io::Error> {
+        let summaries = self.limiter.as_mut().map(RateLimiter::drain).unwrap_or_default();
+        for summary in summaries {
+            self.write_outputs(&summary)?;
+        }
+
+        for sink in self.sinks.iter_mut() {
+            sink.flush()?;
+        }
+
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+//type LoggerLock<'a> = OptionMutexGuard<'a, LoadedLogger>;
+
+/// Determines what happens when a record is submitted to a full background queue.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OverflowPolicy {
+    /// The caller waits until there is space in the queue.
+    #[default]
+    Block,
+    /// The oldest queued record is discarded to make space.
+    DropOldest,
+    /// The new record is discarded.
+    DropNew
+}
+
+#[derive(Default)]
+struct QueueState {
+    records: VecDeque<LoggerWrite>,
+    writing: bool,
+    closed: bool
+}
+
+thread_local! {
+    /// The address of the queue drained by this thread, if it is a background writer. A writer must never wait on its own queue.
+    static WRITER_OF: Cell<usize> = const { Cell::new(0) };
+}
+
+/// A bounded queue of records, drained by the background writer thread.
+struct LogQueue {
+    state: Mutex<QueueState>,
+    /// Notified when records are added, or the queue is closed.
+    available: Condvar,
+    /// Notified when records are taken out, or finished being written.
+    space: Condvar,
+    capacity: usize,
+    policy: OverflowPolicy,
+    dropped: AtomicU64
+}
+impl LogQueue {
+    fn new(capacity: usize, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(QueueState::default()),
+            available: Condvar::new(),
+            space: Condvar::new(),
+            capacity: capacity.max(1),
+            policy,
+            dropped: AtomicU64::new(0)
+        }
+    }
+
+    /// Determines if the current thread is the one writing the records of this queue.
+    fn is_writer(&self) -> bool {
+        WRITER_OF.with(|x| x.get()) == self as *const Self as usize
+    }
+
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        match self.state.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        }
+    }
+
+    /// Places `write` in the queue, applying the overflow policy if it is full. Returns the record back if the queue is closed.
+    ///
+    /// Records submitted by the writer thread (for instance, by a sink that logs) are discarded instead of blocking, as that thread is the one that would make space.
+    fn push(&self, write: LoggerWrite) -> Option<LoggerWrite> {
+        let mut state = self.lock();
+        if state.closed {
+            return Some(write);
+        }
+
+        if state.records.len() >= self.capacity {
+            match self.policy {
+                OverflowPolicy::Block if !self.is_writer() => {
+                    while state.records.len() >= self.capacity && !state.closed {
+                        state = match self.space.wait(state) {
+                            Ok(v) => v,
+                            Err(e) => e.into_inner()
+                        };
+                    }
+                    if state.closed {
+                        return Some(write);
+                    }
+                },
+                OverflowPolicy::DropOldest => {
+                    state.records.pop_front();
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                },
+                OverflowPolicy::Block | OverflowPolicy::DropNew => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    return None;
+                }
+            }
+        }
+
+        state.records.push_back(write);
+        self.available.notify_one();
+        None
+    }
+    /// Waits, at most, `timeout` for records to write, returning `None` once the queue is closed and empty. The records returned are empty if the time ran out.
+    fn take(&self, timeout: Duration) -> Option<Vec<LoggerWrite>> {
+        let mut state = self.lock();
+        state.writing = false;
+        self.space.notify_all();
+
+        let deadline = Instant::now() + timeout;
+        while state.records.is_empty() && !state.closed {
+            let Some(remaining) = deadline.checked_duration_since(Instant::now()) else {
+                return Some(Vec::new());
+            };
+            state = match self.available.wait_timeout(state, remaining) {
+                Ok(v) => v.0,
+                Err(e) => e.into_inner().0
+            };
+        }
+
+        if state.records.is_empty() {
+            return None;
+        }
+
+        state.writing = true;
+        let result = state.records.drain(..).collect();
+        self.space.notify_all();
+        Some(result)
+    }
+    /// Waits until every queued record has been written, or the queue is closed.
+    fn wait_empty(&self) {
+        let mut state = self.lock();
+        while (!state.records.is_empty() || state.writing) && !state.closed {
+            state = match self.space.wait(state) {
+                Ok(v) => v,
+                Err(e) => e.into_inner()
+            };
+        }
+    }
+    fn close(&self) {
+        self.lock().closed = true;
+        self.available.notify_all();
+        self.space.notify_all();
+    }
+}
+
+/// How long the panic hook waits for a logger that is in use by another thread.
+pub const PANIC_LOCK_WAIT: Duration = Duration::from_millis(500);
+
+/// How often the background writer wakes up without records, to report records suppressed by the rate limiter.
+const REPORT_INTERVAL: Duration = Duration::from_millis(250);
+
+/// The queue & thread used by a logger in the background writing mode.
+struct BackgroundWriter {
+    queue: Arc<LogQueue>,
+    thread: Option<JoinHandle<()>>
+}
+impl BackgroundWriter {
+    fn start(data: Arc<Mutex<Option<LoadedLogger>>>, name: Option<Arc<str>>, capacity: usize, policy: OverflowPolicy) -> Result<Self, std::io::Error> {
+        let queue = Arc::new(LogQueue::new(capacity, policy));
+        let thread_queue = queue.clone();
+        let thread = std::thread::Builder::new()
+            .name("log-writer".to_string())
+            .spawn(move || {
+                WRITER_OF.with(|x| x.set(Arc::as_ptr(&thread_queue) as usize));
+                let _close = CloseOnExit(thread_queue.clone());
+
+                while let Some(records) = thread_queue.take(REPORT_INTERVAL) {
+                    // A panicking sink loses the rest of the batch, but does not stop the writer.
+                    let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                        let mut lock = match data.lock() {
+                            Ok(v) => v,
+                            Err(e) => e.into_inner()
+                        };
+                        for write in records {
+                            write_or_close(&mut lock, name.as_deref(), write);
+                        }
+                        report_or_close(&mut lock, name.as_deref());
+                    }));
+                    if result.is_err() {
+                        eprintln!("the background writer of log '{}' panicked while writing. The records being written were lost", name.as_deref().unwrap_or("<global>"));
+                    }
+                }
+            })?;
+
+        Ok(Self {
+            queue,
+            thread: Some(thread)
+        })
+    }
+}
+/// Closes the queue when the writer thread exits, so that nothing waits on a thread that is gone.
+struct CloseOnExit(Arc<LogQueue>);
+impl Drop for CloseOnExit {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+impl Drop for BackgroundWriter {
+    fn drop(&mut self) {
+        self.queue.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// A record being built in parts, which is submitted to its logger when dropped. See `Logger::record`.
+#[must_use = "the record is submitted as soon as the guard is dropped"]
+pub struct RecordGuard<'a> {
+    logger: &'a Logger,
+    write: Option<LoggerWrite>
+}
+impl Drop for RecordGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(write) = self.write.take() {
+            self.logger.submit(write)
+        }
+    }
+}
+impl RecordGuard<'_> {
+    /// Appends `value` to the record's contents, using its `Display` formatting.
+    pub fn push<T: Display + ?Sized>(&mut self, value: &T) -> &mut Self {
+        if let Some(write) = self.write.as_mut() {
+            write.push(value);
+        }
+        self
+    }
+    /// Attaches a key-value field to the record.
+    pub fn push_field<T: Serialize + ?Sized>(&mut self, key: &str, value: &T) -> &mut Self {
+        if let Some(write) = self.write.as_mut() {
+            write.push_field(key, value);
+        }
+        self
+    }
+    /// Sets the module that produced the record.
+    pub fn with_module(mut self, module: &str) -> Self {
+        self.write = self.write.take().map(|x| x.with_module(module));
+        self
+    }
+
+    /// The record built so far.
+    pub fn write(&self) -> Option<&LoggerWrite> {
+        self.write.as_ref()
+    }
+    /// Discards the record, so that nothing is submitted.
+    pub fn cancel(mut self) {
+        self.write = None;
+    }
+}
+
+/// Writes `write` into the loaded logger, if any. If writing fails, the main file is closed (keeping the sinks), and the error is reported to stderr.
+fn write_or_close(logger: &mut Option<LoadedLogger>, name: Option<&str>, write: LoggerWrite) {
+    let Some(loaded) = logger.as_mut() else {
+        return;
+    };
+
+    if write.level() >= loaded.min_level() && let Err(e) = loaded.write_record(&write) {
+        close_after_error(logger, name, e);
+    }
+}
+/// Reports the records suppressed by the loaded logger's rate limiter, if any, in the same way as `write_or_close`.
+fn report_or_close(logger: &mut Option<LoadedLogger>, name: Option<&str>) {
+    if let Some(loaded) = logger.as_mut() && let Err(e) = loaded.report_suppressed() {
+        close_after_error(logger, name, e);
+    }
+}
+/// Closes the main file of the loaded logger after it failed to be written to, in the same way as `log_direct_write`. Failing sinks are already removed by `LoadedLogger`.
+fn close_after_error(logger: &mut Option<LoadedLogger>, name: Option<&str>, error: std::io::Error) {
+    eprintln!("unable to write to log '{}' because of '{:?}'. Log file will be closed", name.unwrap_or("<global>"), error);
+    if let Some(loaded) = logger.as_mut() {
+        loaded.file = None;
+    }
+}
+
+/// A thread safe structure used to load and manage a logger. Clones of a logger are handles to the same logger, sharing its outputs, levels & background writer.
+#[derive(Clone)]
+pub struct Logger {
+    name: Option<Arc<str>>,
+    data: Arc<Mutex<Option<LoadedLogger>>>,
+    background: Arc<RwLock<Option<BackgroundWriter>>>
+}
+impl Default for Logger {
+    fn default() -> Self {
+        Self {
+            name: None,
+            data: Arc::new(Mutex::new(None)),
+            background: Arc::new(RwLock::new(None))
+        }
+    }
+}
+impl MutexProvider for Logger {
+    type Data = Option<LoadedLogger>;
+
+    fn access_raw(&self) -> ProtectedAccess<'_, Arc<Mutex<Self::Data>>> {
+        ProtectedAccess::new(&self.data)
+    }
+}
+/// `pass` and `reset` also update the state derived from the logger's levels, such as the maximum level of the `log` facade.
+impl OptionMutexProvider<LoadedLogger> for Logger {
+    fn pass(&self, value: LoadedLogger) {
+        let mut guard = match self.data.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner()
+        };
+
+        self.levels_changed(Some(value.min_level()));
+        *guard = Some(value);
+        self.data.clear_poison();
+    }
+    fn reset(&self) {
+        let mut guard = match self.data.lock() {
+            Ok(g) => g,
+            Err(e) => e.into_inner()
+        };
+
+        self.levels_changed(None);
+        *guard = None;
+        self.data.clear_poison();
+    }
+}
+impl Logger {
+    /// Creates a logger, independent of `LOG`, with a name used when reporting its errors. See `named_logger` for sharing a logger by name.
+    pub fn named(name: &str) -> Self {
+        Self {
+            name: Some(Arc::from(name)),
+            ..Self::default()
+        }
+    }
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// Opens the log file, truncating it if it already exists.
+    pub fn open<T: AsRef<Path>>(&self, path: T, level: LoggerLevel, redirect: LoggerRedirect) -> Result<(), std::io::Error> {
+        let file = LogFile::open(path, false)?;
+
+        self.pass(LoadedLogger::with_file(file, level, redirect));
+        Ok(())
+    }
+    /// Opens the log file, keeping the existing contents.
+    pub fn open_append<T: AsRef<Path>>(&self, path: T, level: LoggerLevel, redirect: LoggerRedirect) -> Result<(), std::io::Error> {
+        let file = LogFile::open(path, true)?;
+
+        self.pass(LoadedLogger::with_file(file, level, redirect));
+        Ok(())
+    }
+    /// Opens the log file, keeping the existing contents, and rotates it according to `rotation`.
+    pub fn open_rotating<T: AsRef<Path>>(&self, path: T, level: LoggerLevel, redirect: LoggerRedirect, rotation: RotationPolicy) -> Result<(), std::io::Error> {
+        let mut file = LogFile::open(path, true)?;
+        file.set_rotation(Some(rotation));
+
+        self.pass(LoadedLogger::with_file(file, level, redirect));
+        Ok(())
+    }
+
+    /// Loads a logger that only writes to `sinks`.
+    pub fn open_sinks(&self, sinks: Vec<Box<dyn LogSink>>) {
+        self.pass(LoadedLogger::from_sinks(sinks));
+    }
+    /// Adds a sink to the logger. Returns false if the logger is not open.
+    pub fn add_sink<S: LogSink + 'static>(&self, sink: S) -> bool {
+        self.modify(|x| x.add_sink(sink)).is_some()
+    }
+
+    /// Runs `f` on the loaded logger while holding its lock, so that no record is written in the middle of the change. Returns `None` if the logger is not open.
+    pub fn modify<R, F>(&self, f: F) -> Option<R> where F: FnOnce(&mut LoadedLogger) -> R {
+        let mut lock = self.access();
+        let result = lock.access_mut().map(f);
+        self.levels_changed(lock.access().map(|x| x.min_level()));
+        result
+    }
+    /// Called while the lock is held, after the loaded logger is replaced or modified, with its new minimum level (`None` if it is closed).
+    #[cfg_attr(not(feature="log"), allow(unused_variables))]
+    fn levels_changed(&self, min_level: Option<LoggerLevel>) {
+        #[cfg(feature="log")]
+        log_facade::sync_max_level(self, min_level);
+    }
+    /// Sets the level of the logger's main file and redirect. Returns false if the logger is not open.
+    pub fn set_level(&self, level: LoggerLevel) -> bool {
+        self.modify(|x| x.set_level(level)).is_some()
+    }
+    /// Sets the logger's redirect. Returns false if the logger is not open.
+    pub fn set_redirect(&self, redirect: LoggerRedirect) -> bool {
+        self.modify(|x| x.set_redirect(redirect)).is_some()
+    }
+    /// Sets the layout of the logger's main file. Returns false if the logger is not open.
+    pub fn set_format(&self, format: LogFormat) -> bool {
+        self.modify(|x| x.set_format(format)).is_some()
+    }
+    /// Switches the logger's main file to the one at `path`. No records are lost, as the new file is opened before the old one is closed.
+    pub fn set_path<P: AsRef<Path>>(&self, path: P, append: bool) -> Result<(), IOError> {
+        self.modify(|x| x.set_path(path, append))
+            .ok_or_else(|| IOError::Core(OperationError::new("set log path", "the logger is not open").into()))?
+            .map_err(IOError::from)
+    }
+    /// Closes and reopens the logger's main file at its path. This should be called after the file has been moved by an external tool, such as logrotate.
+    pub fn reopen(&self) -> Result<(), IOError> {
+        self.modify(|x| x.reopen())
+            .ok_or_else(|| IOError::Core(OperationError::new("reopen log", "the logger is not open").into()))?
+            .map_err(IOError::from)
+    }
+
+    pub fn level(&self) -> Option<LoggerLevel> {
+        let data = self.data.lock().unwrap();
+        data.as_ref().map(|x| x.level())
+    }
+    /// The lowest level that any output of the logger accepts, if it is open.
+    pub fn min_level(&self) -> Option<LoggerLevel> {
+        let lock = self.access();
+        lock.access().map(|x| x.min_level())
+    }
+    /// Determines if a record at `level` would be written by the logger, or stored by a `LogCapture` on this thread. The macros use this to skip building records that would be discarded.
+    /// This does not wait for the logger's lock. If it is held elsewhere, this returns true, and the level is checked again when the record is submitted.
+    pub fn enabled(&self, level: LoggerLevel) -> bool {
+        if is_capturing() {
+            return true;
+        }
+
+        match self.data.try_lock() {
+            Ok(v) => v.as_ref().is_some_and(|x| level >= x.min_level()),
+            Err(TryLockError::Poisoned(e)) => e.into_inner().as_ref().is_some_and(|x| level >= x.min_level()),
+            Err(TryLockError::WouldBlock) => true
+        }
+    }
+    /// Sets the rate limiter of the logger. Returns false if the logger is not open.
+    pub fn set_rate_limiter(&self, limiter: Option<RateLimiter>) -> Result<bool, std::io::Error> {
+        self.modify(|x| x.set_rate_limiter(limiter)).transpose().map(|x| x.is_some())
+    }
+    /// Sets the per-module filter of the logger. Returns false if the logger is not open.
+    pub fn set_filter(&self, filter: Option<LevelFilter>) -> bool {
+        self.modify(|x| x.set_filter(filter)).is_some()
+    }
+
+    /// Switches the logger into the background writing mode. Records submitted are placed into a queue of, at most, `capacity` records, which a dedicated thread writes out.
+    /// If the logger is already in this mode, the existing queue is drained and replaced.
+    pub fn start_background(&self, capacity: usize, policy: OverflowPolicy) -> Result<(), std::io::Error> {
+        let writer = BackgroundWriter::start(self.data.clone(), self.name.clone(), capacity, policy)?;
+
+        let mut background = match self.background.write() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+        *background = Some(writer);
+        Ok(())
+    }
+    /// Writes out all queued records, and returns the logger to writing records as they are submitted.
+    pub fn stop_background(&self) {
+        let writer = match self.background.write() {
+            Ok(mut v) => v.take(),
+            Err(e) => e.into_inner().take()
+        };
+        drop(writer);
+    }
+    /// Determines if the logger is in the background writing mode.
+    pub fn is_background(&self) -> bool {
+        match self.background.read() {
+            Ok(v) => v.is_some(),
+            Err(e) => e.into_inner().is_some()
+        }
+    }
+    /// The number of records discarded because the background queue was full.
+    pub fn dropped(&self) -> u64 {
+        match self.background.read() {
+            Ok(v) => v.as_ref().map(|x| x.queue.dropped.load(Ordering::Relaxed)).unwrap_or(0),
+            Err(e) => e.into_inner().as_ref().map(|x| x.queue.dropped.load(Ordering::Relaxed)).unwrap_or(0)
+        }
+    }
+
+    /// Writes a record from the panic hook, bypassing the background queue. As the lock may be held by the panicking thread, this only waits for it up to `PANIC_LOCK_WAIT`.
+    fn write_panic(&self, write: LoggerWrite) {
+        let write = match try_capture(write) {
+            Some(v) => v,
+            None => return
+        };
+
+        let deadline = Instant::now() + PANIC_LOCK_WAIT;
+        let mut lock = loop {
+            match self.data.try_lock() {
+                Ok(v) => break v,
+                Err(TryLockError::Poisoned(e)) => break e.into_inner(),
+                Err(TryLockError::WouldBlock) if Instant::now() < deadline => std::thread::sleep(Duration::from_millis(1)),
+                Err(TryLockError::WouldBlock) => return
+            }
+        };
+        if let Some(logger) = lock.as_mut() {
+            let _ = logger.write_record(&write);
+            let _ = logger.flush();
+        }
+    }
+
+    /// Starts a record that is built up in parts, and submitted to the logger when the returned guard is dropped.
+    /// The logger is not locked while the record is being built, so values pushed into it may log themselves.
+    /// If the logger would not write a record at `level` (see `enabled`), the guard is empty, and everything pushed into it is ignored.
+    #[track_caller]
+    pub fn record(&self, level: LoggerLevel) -> RecordGuard<'_> {
+        let location = std::panic::Location::caller();
+
+        RecordGuard {
+            logger: self,
+            write: self.enabled(level).then(|| LoggerWrite::now(level, String::new()).with_location(location.file(), location.line()))
+        }
+    }
+
+    /// Submits a record to the logger. In the background writing mode, this only places the record in the queue. Otherwise, or if the writer thread has stopped, the record is written immediately.
+    /// If a `LogCapture` is active on this thread, the record is stored in it instead.
+    pub fn submit(&self, write: LoggerWrite) {
+        let write = match try_capture(write) {
+            Some(v) => v,
+            None => return
+        };
+
+        let write = {
+            let background = match self.background.read() {
+                Ok(v) => v,
+                Err(e) => e.into_inner()
+            };
+            match background.as_ref() {
+                // The writer thread holds the lock while writing, so a record it could not queue is dropped.
+                Some(writer) if writer.queue.is_writer() => {
+                    let _ = writer.queue.push(write);
+                    return;
+                },
+                Some(writer) => match writer.queue.push(write) {
+                    Some(write) => write,
+                    None => return
+                },
+                None => write
+            }
+        };
+
+        let mut lock = match self.data.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+        write_or_close(&mut lock, self.name(), write);
+    }
+    /// Reports the records suppressed by the rate limiter in windows that have already ended. See `LoadedLogger::report_suppressed`.
+    /// This is only needed outside of the background writing mode, where nothing is written while no records are submitted.
+    pub fn report_suppressed(&self) {
+        let mut lock = match self.data.lock() {
+            Ok(v) => v,
+            Err(e) => e.into_inner()
+        };
+        report_or_close(&mut lock, self.name());
+    }
+    /// Waits for all queued records to be written, and then flushes the logger's file & sinks. This should be called before the program exits.
+    pub fn flush(&self) -> Result<(), std::io::Error> {
+        {
+            let background = match self.background.read() {
+                Ok(v) => v,
+                Err(e) => e.into_inner()
+            };
+            if let Some(writer) = background.as_ref() {
+                writer.queue.wait_empty();
+            }
+        }
+
+        let mut lock = self.access();
+        match lock.access_mut() {
+            Some(v) => v.flush(),
+            None => Ok(())
+        }
+    }
+}
+
+/// The name & fields of a span, as stored in the records produced inside of it.
+#[derive(Debug, PartialEq, Clone)]
+pub struct SpanContext {
+    name: String,
+    fields: Vec<(String, Value)>
+}
+impl Display for SpanContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", &self.name)?;
+        if !self.fields.is_empty() {
+            let fields = self.fields.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>();
+            write!(f, "{{{}}}", fields.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+impl SpanContext {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+    pub fn fields(&self) -> &[(String, Value)] {
+        &self.fields
+    }
+
+    fn to_value(&self) -> Value {
+        let mut map = Map::new();
+        map.insert("name".to_string(), Value::String(self.name.clone()));
+        if !self.fields.is_empty() {
+            map.insert("fields".to_string(), Value::Object(self.fields.iter().cloned().collect()));
+        }
+
+        Value::Object(map)
+    }
+}
+
+#[derive(Debug)]
+struct SpanInner {
+    context: SpanContext,
+    module: Option<String>,
+    start: Instant
+}
+impl Drop for SpanInner {
+    fn drop(&mut self) {
+        let elapsed = self.start.elapsed();
+        let mut write = LoggerWrite::now(LoggerLevel::Debug, format!("span {} closed after {:?}", &self.context, elapsed));
+        if let Some(module) = self.module.as_ref() {
+            write = write.with_module(module);
+        }
+        write.push_field("elapsed_ms", &(elapsed.as_secs_f64() * 1000.0));
+
+        log_global_write(write);
+    }
+}
+
+thread_local! {
+    static SPAN_STACK: RefCell<Vec<Span>> = const { RefCell::new(Vec::new()) };
+}
+
+/// The contexts of the spans entered on this thread, from outermost to innermost.
+pub fn current_spans() -> Vec<SpanContext> {
+    SPAN_STACK.with(|x| match x.try_borrow() {
+        Ok(stack) => stack.iter().map(|x| x.inner.context.clone()).collect(),
+        Err(_) => Vec::new()
+    })
+}
+
+/// A named region of work. Records produced while a span is entered are tagged with it, and its duration is logged at `LoggerLevel::Debug` once every handle to it is dropped.
+/// Spans can be cloned, and entered more than once (for example, on each poll of a future).
+#[derive(Debug, Clone)]
+pub struct Span {
+    inner: Arc<SpanInner>
+}
+impl Span {
+    pub fn new(name: &str) -> Self {
+        Self {
+            inner: Arc::new(SpanInner {
+                context: SpanContext {
+                    name: name.to_string(),
+                    fields: Vec::new()
+                },
+                module: None,
+                start: Instant::now()
+            })
+        }
+    }
+    /// Attaches a key-value field to the span. This has no effect if the span has already been cloned.
+    pub fn field<T: Serialize + ?Sized>(mut self, key: &str, value: &T) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.context.fields.push((key.to_string(), field_value(value)));
+        }
+        self
+    }
+    /// Sets the module that created the span, which is used for the record written when it closes. This has no effect if the span has already been cloned.
+    pub fn with_module(mut self, module: &str) -> Self {
+        if let Some(inner) = Arc::get_mut(&mut self.inner) {
+            inner.module = Some(module.to_string());
+        }
+        self
+    }
+
+    pub fn context(&self) -> &SpanContext {
+        &self.inner.context
+    }
+    /// The time since the span was created.
+    pub fn elapsed(&self) -> Duration {
+        self.inner.start.elapsed()
+    }
+
+    /// Enters the span on this thread until the guard is dropped.
+    pub fn enter(&self) -> SpanGuard {
+        SPAN_STACK.with(|x| x.borrow_mut().push(self.clone()));
+        SpanGuard { _thread: std::marker::PhantomData }
+    }
+    /// Enters the span on this thread, closing it once the guard is dropped.
+    pub fn entered(self) -> SpanGuard {
+        self.enter()
+    }
+}
+
+/// Keeps a span entered on the current thread. Guards should be dropped in the reverse order that they were created.
+#[derive(Debug)]
+#[must_use = "the span is exited as soon as the guard is dropped"]
+pub struct SpanGuard {
+    // Guards refer to the stack of the thread they were created on, so they cannot be sent.
+    _thread: std::marker::PhantomData<*const ()>
+}
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let span = SPAN_STACK.with(|x| x.borrow_mut().pop());
+        // The borrow is released before the span is dropped, as closing it writes a record.
+        drop(span);
+    }
+}
+
+#[cfg(feature="async")]
+pub mod log_async {
+    use super::*;
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// A future that enters a span each time it is polled, so records produced across `.await` points are tagged with it.
+    pub struct Instrumented<F> where F: Future {
+        future: Pin<Box<F>>,
+        span: Span
+    }
+    impl<F> Future for Instrumented<F> where F: Future {
+        type Output = F::Output;
+
+        fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+            let _guard = self.span.enter();
+            self.future.as_mut().poll(cx)
+        }
+    }
+    impl<F> Instrumented<F> where F: Future {
+        pub fn span(&self) -> &Span {
+            &self.span
+        }
+    }
+
+    pub trait Instrument: Future + Sized {
+        /// Enters `span` whenever this future is polled.
+        fn instrument(self, span: Span) -> Instrumented<Self> {
+            Instrumented {
+                future: Box::pin(self),
+                span
+            }
+        }
+    }
+    impl<F> Instrument for F where F: Future { }
+
+    /// Spawns a task that reopens the main file of `logger` whenever the process receives `SIGHUP`, which is how tools like logrotate signal that the file was moved.
+    /// This must be called from within a tokio runtime.
+    #[cfg(unix)]
+    pub fn reopen_on_sighup(logger: Logger) -> Result<tokio::task::JoinHandle<()>, std::io::Error> {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut hangup = signal(SignalKind::hangup())?;
+        Ok(tokio::spawn(async move {
+            while hangup.recv().await.is_some() {
+                if let Err(e) = logger.reopen() {
+                    eprintln!("unable to reopen log because of '{}'", e);
+                }
+            }
+        }))
+    }
+}
+#[cfg(feature="async")]
+pub use log_async::*;
+
+#[cfg(feature="log")]
+pub mod log_facade {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    impl From<::log::Level> for LoggerLevel {
+        /// Maps the levels of the `log` crate, with `Trace` becoming `Debug`.
+        fn from(value: ::log::Level) -> Self {
+            match value {
+                ::log::Level::Error => Self::Error,
+                ::log::Level::Warn => Self::Warning,
+                ::log::Level::Info => Self::Info,
+                ::log::Level::Debug | ::log::Level::Trace => Self::Debug
+            }
+        }
+    }
+
+    /// Set once `LOG` is installed as the facade's implementation.
+    static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+    /// The facade's maximum level that lets through the records accepted at `min_level`. As `Trace` is written as `Debug`, a logger accepting `Debug` also accepts `Trace`.
+    fn max_level_for(min_level: Option<LoggerLevel>) -> ::log::LevelFilter {
+        match min_level {
+            None => ::log::LevelFilter::Off,
+            Some(LoggerLevel::Debug) => ::log::LevelFilter::Trace,
+            Some(LoggerLevel::Info) => ::log::LevelFilter::Info,
+            Some(LoggerLevel::Warning) => ::log::LevelFilter::Warn,
+            Some(LoggerLevel::Error | LoggerLevel::Critical) => ::log::LevelFilter::Error
+        }
+    }
+    /// Updates the facade's maximum level when the levels of `LOG` (or a clone of it) change.
+    pub(super) fn sync_max_level(logger: &Logger, min_level: Option<LoggerLevel>) {
+        if INSTALLED.load(Ordering::Acquire) && Arc::ptr_eq(&logger.data, &LOG.data) {
+            ::log::set_max_level(max_level_for(min_level));
+        }
+    }
+
+    impl ::log::Log for Logger {
+        fn enabled(&self, metadata: &::log::Metadata) -> bool {
+            Logger::enabled(self, metadata.level().into())
+        }
+        fn log(&self, record: &::log::Record) {
+            if !::log::Log::enabled(self, record.metadata()) {
+                return;
+            }
+
+            let mut write = LoggerWrite::now(record.level().into(), record.args().to_string())
+                .with_module(record.module_path().unwrap_or(record.target()));
+            if let (Some(file), Some(line)) = (record.file(), record.line()) {
+                write = write.with_location(file, line);
+            }
+
+            self.submit(write)
+        }
+        fn flush(&self) {
+            let _ = Logger::flush(self);
+        }
+    }
+
+    /// Installs the global logger, `LOG`, as the implementation of the `log` crate's facade. Records from other crates are then written the same way as records from the macros of this module.
+    ///
+    /// The facade's maximum level follows the minimum level of `LOG`, so that records it would discard are skipped by the other crates. It is updated when `LOG` is opened, closed, or changed through `modify` (and methods such as `set_level` & `set_filter`).
+    /// Changes made directly through `LOG.access()` are not seen until the next of these calls.
+    pub fn install_log_facade() -> Result<(), ::log::SetLoggerError> {
+        ::log::set_logger(&*LOG)?;
+
+        let lock = LOG.access();
+        INSTALLED.store(true, Ordering::Release);
+        ::log::set_max_level(max_level_for(lock.access().map(|x| x.min_level())));
+        Ok(())
+    }
+}
+#[cfg(feature="log")]
+pub use log_facade::*;
+
+#[cfg(feature="tracing")]
+pub mod log_tracing {
+    use super::*;
+    use tracing::field::{Field, Visit};
+    use tracing::{Event, Subscriber};
+    use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+    impl From<tracing::Level> for LoggerLevel {
+        /// Maps the levels of the `tracing` crate, with `TRACE` becoming `Debug`.
+        fn from(value: tracing::Level) -> Self {
+            match value {
+                tracing::Level::ERROR => Self::Error,
+                tracing::Level::WARN => Self::Warning,
+                tracing::Level::INFO => Self::Info,
+                _ => Self::Debug
+            }
+        }
+    }
+
+    /// Collects the `message` of an event as the contents of a record, and every other field as a key-value field.
+    struct RecordVisitor<'a> {
+        write: &'a mut LoggerWrite
+    }
+    impl Visit for RecordVisitor<'_> {
+        fn record_debug(&mut self, field: &Field, value: &dyn Debug) {
+            match field.name() {
+                "message" => self.write.contents = format!("{:?}", value),
+                name => self.write.push_field(name, &format!("{:?}", value))
+            }
+        }
+        fn record_str(&mut self, field: &Field, value: &str) {
+            match field.name() {
+                "message" => self.write.contents = value.to_string(),
+                name => self.write.push_field(name, value)
+            }
+        }
+        fn record_i64(&mut self, field: &Field, value: i64) {
+            self.write.push_field(field.name(), &value)
+        }
+        fn record_u64(&mut self, field: &Field, value: u64) {
+            self.write.push_field(field.name(), &value)
+        }
+        fn record_f64(&mut self, field: &Field, value: f64) {
+            self.write.push_field(field.name(), &value)
+        }
+        fn record_bool(&mut self, field: &Field, value: bool) {
+            self.write.push_field(field.name(), &value)
+        }
+    }
+
+    /// A `tracing` layer that writes every event into a `Logger`.
+    #[derive(Clone)]
+    pub struct LoggerLayer {
+        logger: Logger
+    }
+    impl Default for LoggerLayer {
+        fn default() -> Self {
+            Self::new(LOG.clone())
+        }
+    }
+    impl LoggerLayer {
+        pub fn new(logger: Logger) -> Self {
+            Self {
+                logger
+            }
+        }
+    }
+    impl<S> Layer<S> for LoggerLayer where S: Subscriber {
+        fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let level = (*metadata.level()).into();
+            if !self.logger.enabled(level) {
+                return;
+            }
+
+            let mut write = LoggerWrite::now(level, String::new())
+                .with_module(metadata.module_path().unwrap_or(metadata.target()));
+            if let (Some(file), Some(line)) = (metadata.file(), metadata.line()) {
+                write = write.with_location(file, line);
+            }
+
+            event.record(&mut RecordVisitor { write: &mut write });
+            self.logger.submit(write)
+        }
+    }
+
+    /// Installs a subscriber that writes every `tracing` event into the global logger, `LOG`.
+    pub fn install_tracing() -> Result<(), tracing::subscriber::SetGlobalDefaultError> {
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(LoggerLayer::default()))
+    }
+}
+#[cfg(feature="tracing")]
+pub use log_tracing::*;
+
+thread_local! {
+    static CAPTURES: RefCell<Vec<Arc<Mutex<Vec<LoggerWrite>>>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Determines if a `LogCapture` is active on this thread.
+fn is_capturing() -> bool {
+    CAPTURES.with(|x| x.try_borrow().is_ok_and(|x| !x.is_empty()))
+}
 
-        self.write = None;
-        Ok(())
+/// Stores `write` in the innermost capture on this thread, returning it back if there is none.
+fn try_capture(write: LoggerWrite) -> Option<LoggerWrite> {
+    let target = CAPTURES.with(|x| x.try_borrow().ok().and_then(|x| x.last().cloned()));
+    match target {
+        Some(records) => {
+            match records.lock() {
+                Ok(mut v) => v.push(write),
+                Err(e) => e.into_inner().push(write)
+            }
+            None
+        },
+        None => Some(write)
     }
+}
 
-    /// Regardless of a log being currently in progress or not, this will direclty write a string into the log file. 
-    pub fn write_direct(&mut self, contents: String, level: LoggerLevel) -> Result<(), std::io::Error> {
-        let write = LoggerWrite::new_str(
-            format!("{}", chrono::Local::now()),
-            contents,
-            level
-        );
-        self.redirect.handle_redirect(&write);
-        let bytes: Vec<u8> = write.into();
-        
-        self.file.write_all(&bytes)?;
-        Ok(())
+/// While this is alive, every record submitted on this thread (including through `LOG` and the macros) is stored in memory instead of being written, regardless of level.
+/// Captures are scoped to the thread that created them, so tests running in parallel do not see each other's records. Captures can be nested, with the innermost receiving the records.
+#[must_use = "records are only captured while this is alive"]
+pub struct LogCapture {
+    records: Arc<Mutex<Vec<LoggerWrite>>>,
+    _thread: std::marker::PhantomData<*const ()>
+}
+impl Drop for LogCapture {
+    fn drop(&mut self) {
+        CAPTURES.with(|x| x.borrow_mut().retain(|v| !Arc::ptr_eq(v, &self.records)));
     }
 }
+impl LogCapture {
+    pub fn start() -> Self {
+        let records = Arc::new(Mutex::new(Vec::new()));
+        CAPTURES.with(|x| x.borrow_mut().push(records.clone()));
 
-//type LoggerLock<'a> = OptionMutexGuard<'a, LoadedLogger>;
-
-/// A global safe structure used to load and manage a logger.
-pub struct Logger {
-    data: Arc<Mutex<Option<LoadedLogger>>>
-}
-impl Default for Logger {
-    fn default() -> Self {
         Self {
-            data: Arc::new(Mutex::new(None))
+            records,
+            _thread: std::marker::PhantomData
         }
     }
-}
-impl MutexProvider for Logger {
-    type Data = Option<LoadedLogger>;
 
-    fn access_raw(&self) -> ProtectedAccess<'_, Arc<Mutex<Self::Data>>> {
-        ProtectedAccess::new(&self.data)
+    /// A copy of the records captured so far, in the order they were submitted.
+    pub fn records(&self) -> Vec<LoggerWrite> {
+        match self.records.lock() {
+            Ok(v) => v.clone(),
+            Err(e) => e.into_inner().clone()
+        }
+    }
+    pub fn clear(&self) {
+        match self.records.lock() {
+            Ok(mut v) => v.clear(),
+            Err(e) => e.into_inner().clear()
+        }
     }
-}
-impl OptionMutexProvider<LoadedLogger> for Logger { }
-impl Logger {
-    pub fn open<T: AsRef<Path>>(&self, path: T, level: LoggerLevel, redirect: LoggerRedirect) -> Result<(), std::io::Error> {
-        let file = File::create(path)?;
 
-        let loaded = LoadedLogger::new(
-            file,
-            level,
-            redirect
-        );
+    /// The number of records captured at `level`.
+    pub fn count(&self, level: LoggerLevel) -> usize {
+        self.records().iter().filter(|x| x.level() == level).count()
+    }
+    /// Determines if a record at `level` with contents containing `pattern` was captured.
+    pub fn contains(&self, level: LoggerLevel, pattern: &str) -> bool {
+        self.records().iter().any(|x| x.level() == level && x.contents().contains(pattern))
+    }
 
-        self.pass(loaded);
-        Ok(())
+    /// Panics, listing the captured records, if no record at `level` contains `pattern`.
+    #[track_caller]
+    pub fn assert_contains(&self, level: LoggerLevel, pattern: &str) {
+        if !self.contains(level, pattern) {
+            panic!("expected a {:?} record containing '{}', but captured:\n{}", level, pattern, self.describe());
+        }
+    }
+    /// Panics, listing the captured records, if any record at `level` contains `pattern`.
+    #[track_caller]
+    pub fn assert_not_contains(&self, level: LoggerLevel, pattern: &str) {
+        if self.contains(level, pattern) {
+            panic!("expected no {:?} record containing '{}', but captured:\n{}", level, pattern, self.describe());
+        }
     }
 
-    pub fn level(&self) -> Option<LoggerLevel> {
-        let data = self.data.lock().unwrap();
-        data.as_ref().map(|x| x.level())
+    fn describe(&self) -> String {
+        self.records().iter().map(|x| format!("  {:?} {}", x.level(), x.contents())).collect::<Vec<_>>().join("\n")
     }
 }
 
+/// Installs a panic hook that writes a `LoggerLevel::Critical` record of each panic into the global logger, `LOG`, and flushes it, before running the previous hook.
+/// The record contains the thread name, location & message of the panic, and a backtrace field if `backtrace` is set.
+/// The record is written directly, rather than through the background queue (if any), so it is not lost if the process exits. 
+/// If the logger stays locked for longer than `PANIC_LOCK_WAIT` (for instance, because the panicking thread is the one using it), the record is not written.
+pub fn install_panic_hook(backtrace: bool) {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        let thread = std::thread::current();
+        let message = info.payload_as_str().unwrap_or("Box<dyn Any>");
+        let mut write = match info.location() {
+            Some(location) => LoggerWrite::now(
+                LoggerLevel::Critical,
+                format!("thread '{}' panicked at {}: {}", thread.name().unwrap_or("<unnamed>"), location, message)
+            ).with_location(location.file(), location.line()),
+            None => LoggerWrite::now(LoggerLevel::Critical, format!("thread '{}' panicked: {}", thread.name().unwrap_or("<unnamed>"), message))
+        };
+        if backtrace {
+            write.push_field("backtrace", &std::backtrace::Backtrace::force_capture().to_string());
+        }
+
+        LOG.write_panic(write);
+        previous(info);
+    }));
+}
+
 lazy_static! {
     pub static ref LOG: Logger = Logger::default();
+    static ref NAMED_LOGGERS: Mutex<HashMap<String, Logger>> = Mutex::new(HashMap::new());
 }
 
-pub fn log_global(level: LoggerLevel, contents: String) {
-    if !LOG.is_open() {
-        return;
-    }
+/// Gets a handle to the logger called `name`, creating it (closed) if it does not exist yet. Every call with the same name refers to the same logger.
+pub fn named_logger(name: &str) -> Logger {
+    let mut loggers = match NAMED_LOGGERS.lock() {
+        Ok(v) => v,
+        Err(e) => e.into_inner()
+    };
 
-    let mut lock = LOG.access();
-    match lock.access_mut() {
-        Some(v) => log_direct(v, level, contents),
-        None => return
-    }
+    loggers.entry(name.to_string()).or_insert_with(|| Logger::named(name)).clone()
+}
+
+pub fn log_global(level: LoggerLevel, contents: String) {
+    log_global_write(LoggerWrite::now(level, contents))
+}
+/// Writes a complete record into the global logger, if it is open.
+pub fn log_global_write(write: LoggerWrite) {
+    LOG.submit(write)
+}
+/// Determines if a record at `level` would be written by `LOG`. See `Logger::enabled`.
+pub fn log_global_enabled(level: LoggerLevel) -> bool {
+    LOG.enabled(level)
 }
 pub fn log_direct(logger: &mut LoadedLogger, level: LoggerLevel, contents: String) {
-    if level >= logger.level() {
-        if let Err(e) = logger.write_direct(contents, level) {
-            eprintln!("unable to end log because of '{:?}'. Log will be closed", e);
-            LOG.reset();
-        }
+    log_direct_write(logger, LoggerWrite::now(level, contents))
+}
+/// Writes a complete record into `logger`, if its level is high enough. If the main file cannot be written to, it is closed, and the error is reported to stderr.
+pub fn log_direct_write(logger: &mut LoadedLogger, write: LoggerWrite) {
+    if write.level() >= logger.min_level() && let Err(e) = logger.write_record(&write) {
+        eprintln!("unable to write to log because of '{:?}'. Log file will be closed", e);
+        logger.file = None;
     }
 }
 
@@ -311,36 +2541,109 @@ macro_rules! collapse_level {
     }
 }
 
+/// Creates a `Span` with the calling module, and optional key-value fields, such as `span!("request", id = 5)`.
+#[macro_export]
+macro_rules! span {
+    ($name: expr $(, $key: ident = $value: expr)* $(,)?) => {
+        {
+            $crate::log::Span::new($name)
+                .with_module(module_path!())
+                $(.field(stringify!($key), &$value))*
+        }
+    };
+}
+
+/// Starts a record on the global logger that is built up in parts, and written when the guard is dropped, such as
+/// `let mut rec = log_record!(LoggerLevel::Info); rec.push("user ").push(&name);`.
+#[macro_export]
+macro_rules! log_record {
+    ($level: expr) => {
+        $crate::log::LOG.record($crate::collapse_level!($level)).with_module(module_path!())
+    };
+}
+
 /// A macro that allows for shorthand with logger writting. The callee must sepecify the level as `LoggerLevel`, and the message.
-/// Note that this macro will report errors, as they happen. However, if the logger is not open (`logger.is_open() == false`), it will do nothing. 
+/// Key-value fields can be placed before the message, such as `logger_write!(LoggerLevel::Info, user_id = 5, "login")`. The values must implement `Serialize`.
+/// The record is written to `LOG`, unless another `Logger` is given first, such as `logger_write!(logger: &db_log, LoggerLevel::Info, "connected")`.
+/// Note that this macro will report errors, as they happen. However, if the logger is not open (`logger.is_open() == false`), or would not write a record at this level, it will do nothing, and the message is not formatted. 
 /// This macro will evaluate the arguments *before* aquiring the lock to the logger. This is to prevent deadlocks, where an argument calls something with the logger.
 #[macro_export]
 macro_rules! logger_write {
+    (logger: $logger: expr, $level: expr, $($key: ident = $value: expr),+ , $fmt: literal $($arg:tt)*) => {
+        {
+            let logger = &$logger;
+            let level = $crate::collapse_level!($level);
+
+            if logger.enabled(level) {
+                let contents: String = format!($fmt $($arg)*);
+                let mut write = $crate::log::LoggerWrite::now(level, contents).with_module(module_path!()).with_location(file!(), line!());
+                $(
+                    write.push_field(stringify!($key), &$value);
+                )+
+
+                logger.submit(write);
+            }
+        }
+    };
+    (logger: $logger: expr, $level: expr, $($arg:tt)*) => {
+        {
+            let logger = &$logger;
+            let level = $crate::collapse_level!($level);
+
+            if logger.enabled(level) {
+                let contents: String = format!($($arg)*);
+                logger.submit($crate::log::LoggerWrite::now(level, contents).with_module(module_path!()).with_location(file!(), line!()));
+            }
+        }
+    };
+    ($level: expr, $($key: ident = $value: expr),+ , $fmt: literal $($arg:tt)*) => {
+        {
+            let level = $crate::collapse_level!($level);
+
+            if $crate::log::log_global_enabled(level) {
+                let contents: String = format!($fmt $($arg)*);
+                let mut write = $crate::log::LoggerWrite::now(level, contents).with_module(module_path!()).with_location(file!(), line!());
+                $(
+                    write.push_field(stringify!($key), &$value);
+                )+
+
+                $crate::log::log_global_write(write);
+            }
+        }
+    };
     ($level: expr, $($arg:tt)*) => {
         {
-            let contents: String = format!($($arg)*);
             let level = $crate::collapse_level!($level);
-            
-            
-            $crate::log::log_global(level, contents);
+
+            if $crate::log::log_global_enabled(level) {
+                let contents: String = format!($($arg)*);
+                $crate::log::log_global_write($crate::log::LoggerWrite::now(level, contents).with_module(module_path!()).with_location(file!(), line!()));
+            }
         }
     };
     ($log: expr, $level: expr, $($arg:tt)*) => {
         {
-            let contents: String = format!($($arg)*);
+            let log: &mut $crate::log::LoadedLogger = $log;
             let level = $crate::collapse_level!($level);
 
-            $crate::log::log_direct($log, level, contents);
+            if level >= log.min_level() {
+                let contents: String = format!($($arg)*);
+                $crate::log::log_direct_write(log, $crate::log::LoggerWrite::now(level, contents).with_module(module_path!()).with_location(file!(), line!()));
+            }
         }
     };
 }
 /// Writes to the logger with `LoggerLevel::Debug`. Equivalent to `logger_write!(LoggerLevel::Debug, _)`
 #[macro_export]
 macro_rules! log_debug {
+    (logger: $logger: expr, $($arg:tt)*) => {
+        {
+            $crate::logger_write!(logger: $logger, $crate::log::LoggerLevel::Debug, $($arg)*)
+        }
+    };
     ($($arg:tt)*) => {
         {
-            use $crate::logger_write;
-            logger_write!($crate::log::LoggerLevel::Debug, $($arg)*)
+            $crate::logger_write!($crate::log::LoggerLevel::Debug, $($arg)*)
         }
     };
     ($log: expr, $($arg:tt)*) => {
@@ -352,10 +2655,14 @@ macro_rules! log_debug {
 /// Writes to the logger with `LoggerLevel::Info`. Equivalent to `logger_write!(LoggerLevel::Info, _)`
 #[macro_export]
 macro_rules! log_info {
+    (logger: $logger: expr, $($arg:tt)*) => {
+        {
+            $crate::logger_write!(logger: $logger, $crate::log::LoggerLevel::Info, $($arg)*)
+        }
+    };
     ($($arg:tt)*) => {
         {
-            use $crate::logger_write;
-            logger_write!($crate::log::LoggerLevel::Info, $($arg)*)
+            $crate::logger_write!($crate::log::LoggerLevel::Info, $($arg)*)
         }
     };
     ($log: expr, $($arg:tt)*) => {
@@ -367,10 +2674,14 @@ macro_rules! log_info {
 /// Writes to the logger with `LoggerLevel::Warning`. Equivalent to `logger_write!(LoggerLevel::Warning, _)`
 #[macro_export]
 macro_rules! log_warning {
+    (logger: $logger: expr, $($arg:tt)*) => {
+        {
+            $crate::logger_write!(logger: $logger, $crate::log::LoggerLevel::Warning, $($arg)*)
+        }
+    };
     ($($arg:tt)*) => {
         {
-            use $crate::logger_write;
-            logger_write!($crate::log::LoggerLevel::Warning, $($arg)*)
+            $crate::logger_write!($crate::log::LoggerLevel::Warning, $($arg)*)
         }
     };
     ($log: expr, $($arg:tt)*) => {
@@ -382,10 +2693,14 @@ macro_rules! log_warning {
 /// Writes to the logger with `LoggerLevel::Error`. Equivalent to `logger_write!(LoggerLevel::Error, _)`
 #[macro_export]
 macro_rules! log_error {
+    (logger: $logger: expr, $($arg:tt)*) => {
+        {
+            $crate::logger_write!(logger: $logger, $crate::log::LoggerLevel::Error, $($arg)*)
+        }
+    };
     ($($arg:tt)*) => {
         {
-            use $crate::logger_write;
-            logger_write!($crate::log::LoggerLevel::Error, $($arg)*)
+            $crate::logger_write!($crate::log::LoggerLevel::Error, $($arg)*)
         }
     };
     ($log: expr, $($arg:tt)*) => {
@@ -397,10 +2712,14 @@ macro_rules! log_error {
 /// Writes to the logger with `LoggerLevel::Critical`. Equivalent to `logger_write!(LoggerLevel::Critical, _)`
 #[macro_export]
 macro_rules! log_critical {
+    (logger: $logger: expr, $($arg:tt)*) => {
+        {
+            $crate::logger_write!(logger: $logger, $crate::log::LoggerLevel::Critical, $($arg)*)
+        }
+    };
     ($($arg:tt)*) => {
         {
-            use $crate::logger_write;
-            logger_write!($crate::log::LoggerLevel::Critical, $($arg)*)
+            $crate::logger_write!($crate::log::LoggerLevel::Critical, $($arg)*)
         }
     };
     ($log: expr, $($arg:tt)*) => {
@@ -428,6 +2747,567 @@ fn test_logger_write() {
     log_error!("hello 2");
     log_critical!("hello 2");
 
+    log_info!(user_id = 5, name = "user", "login {}", 3);
+
+    // `LOG` is only opened by this test, so the facade's maximum level is checked here.
+    #[cfg(feature="log")]
+    {
+        install_log_facade().unwrap();
+        assert_eq!(::log::max_level(), ::log::LevelFilter::Trace);
+        LOG.set_level(LoggerLevel::Warning);
+        assert_eq!(::log::max_level(), ::log::LevelFilter::Warn);
+        assert!(!::log::log_enabled!(::log::Level::Info));
+        LOG.set_filter(Some("dep=info".parse().unwrap()));
+        assert_eq!(::log::max_level(), ::log::LevelFilter::Info);
+    }
+
     LOG.reset();
     assert!(!LOG.is_open());
-}
\ No newline at end of file
+    #[cfg(feature="log")]
+    assert_eq!(::log::max_level(), ::log::LevelFilter::Off);
+}
+
+#[test]
+fn test_log_formats() {
+    let mut write = LoggerWrite::new_str("now".to_string(), "login".to_string(), LoggerLevel::Info);
+    write.push_field("user_id", &5);
+    write.push_field("name", "user");
+
+    assert_eq!(write.render(&LogFormat::Text), "now INFO login user_id=5 name=\"user\"\n");
+    let json: Value = serde_json::from_str(&write.render(&LogFormat::Json)).unwrap();
+    assert_eq!(json, serde_json::json!({ "time": "now", "level": "INFO", "message": "login", "fields": { "user_id": 5, "name": "user" } }));
+}
+
+#[test]
+fn test_log_template() {
+    let template: LineTemplate = "{level} [{thread}] {module} ({file}:{line}) {{{task}}} {message} {fields}".parse().unwrap();
+    assert!("{level} {missing}".parse::<LineTemplate>().is_err());
+    assert!("{level".parse::<LineTemplate>().is_err());
+
+    let mut write = LoggerWrite::now(LoggerLevel::Warning, "slow".to_string())
+        .with_module("exdisj::net")
+        .with_location("src/net.rs", 12);
+    write.push_field("ms", &40);
+
+    let thread = std::thread::current().name().unwrap().to_string();
+    assert_eq!(write.render(&LogFormat::Template(template)), format!("WARNING [{thread}] exdisj::net (src/net.rs:12) {{-}} slow ms=40\n"));
+    assert!(write.thread_id().is_some());
+}
+
+#[test]
+fn test_log_spans() {
+    let outer = crate::span!("request", id = 5).entered();
+    {
+        let _inner = Span::new("db").entered();
+        let write = LoggerWrite::now(LoggerLevel::Info, "query".to_string());
+        assert_eq!(write.spans().len(), 2);
+
+        let text = write.render(&LogFormat::Text);
+        assert!(text.ends_with(" INFO request{id=5}:db: query\n"), "{}", text);
+    }
+    assert_eq!(current_spans().len(), 1);
+    assert_eq!(current_spans()[0].fields(), &[("id".to_string(), Value::from(5))]);
+
+    drop(outer);
+    assert!(current_spans().is_empty());
+}
+
+#[cfg(feature="async")]
+#[tokio::test]
+async fn test_log_instrument() {
+    let spans = async {
+        tokio::task::yield_now().await;
+        current_spans()
+    }.instrument(Span::new("task")).await;
+
+    assert_eq!(spans.len(), 1);
+    assert_eq!(spans[0].name(), "task");
+    assert!(current_spans().is_empty());
+}
+
+#[cfg(feature="log")]
+#[test]
+fn test_log_facade() {
+    use ::log::Log;
+
+    let sink = MemorySink::new(LoggerLevel::Info, 10);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+
+    logger.log(&::log::Record::builder().level(::log::Level::Warn).module_path(Some("dep::client")).args(format_args!("retrying {}", 2)).build());
+    logger.log(&::log::Record::builder().level(::log::Level::Trace).args(format_args!("ignored")).build());
+
+    let records = sink.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].level(), LoggerLevel::Warning);
+    assert_eq!(records[0].module(), Some("dep::client"));
+    assert_eq!(records[0].contents(), "retrying 2");
+}
+
+#[cfg(feature="tracing")]
+#[test]
+fn test_log_tracing() {
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let sink = MemorySink::new(LoggerLevel::Debug, 10);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+
+    tracing::subscriber::with_default(tracing_subscriber::registry().with(LoggerLayer::new(logger.clone())), || {
+        tracing::info!(attempt = 3, "connected to {}", "server");
+        tracing::trace!("detail");
+    });
+
+    let records = sink.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].contents(), "connected to server");
+    assert_eq!(records[0].fields(), &[("attempt".to_string(), Value::from(3))]);
+    assert_eq!(records[1].level(), LoggerLevel::Debug);
+}
+
+#[test]
+fn test_log_record() {
+    let sink = MemorySink::new(LoggerLevel::Debug, 10);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+
+    {
+        let mut rec = logger.record(LoggerLevel::Info).with_module("app");
+        rec.push("user ").push(&"admin").push(" logged in ").push(&3);
+        rec.push_field("ok", &true);
+    }
+    logger.record(LoggerLevel::Error).cancel();
+    assert!(Logger::default().record(LoggerLevel::Critical).write().is_none());
+
+    {
+        let mut lock = logger.access();
+        let loaded = lock.access_mut().unwrap();
+        loaded.start_log(LoggerLevel::Warning).unwrap();
+        loaded.write("parts ");
+        loaded.write(&1);
+        loaded.end_log().unwrap();
+        assert!(!loaded.is_writing());
+    }
+
+    let records = sink.records();
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[0].contents(), "user admin logged in 3");
+    assert_eq!(records[0].module(), Some("app"));
+    assert_eq!(records[0].file(), Some(file!()));
+    assert_eq!(records[1].contents(), "parts 1");
+    assert_eq!(records[1].level(), LoggerLevel::Warning);
+
+    // Records below the logger's level are not built, so their arguments are not evaluated.
+    let formatted = Cell::new(0);
+    let count = |x: &str| { formatted.set(formatted.get() + 1); x.to_string() };
+    let quiet = Logger::default();
+    quiet.open_sinks(vec![Box::new(MemorySink::new(LoggerLevel::Warning, 10))]);
+    log_info!(logger: &quiet, "{}", count("skipped"));
+    log_error!(logger: &quiet, "{}", count("written"));
+    assert_eq!(formatted.get(), 1);
+}
+
+#[test]
+fn test_log_capture() {
+    let capture = LogCapture::start();
+    log_warning!("disk {} is almost full", "/dev/sda");
+    log_debug!("checked disks");
+
+    capture.assert_contains(LoggerLevel::Warning, "almost full");
+    capture.assert_not_contains(LoggerLevel::Error, "almost full");
+    assert_eq!(capture.count(LoggerLevel::Debug), 1);
+
+    let inner = LogCapture::start();
+    log_error!("failed");
+    assert!(inner.contains(LoggerLevel::Error, "failed"));
+    assert!(!capture.contains(LoggerLevel::Error, "failed"));
+    drop(inner);
+
+    std::thread::spawn(|| log_error!("other thread")).join().unwrap();
+    assert_eq!(capture.records().len(), 2);
+}
+
+#[test]
+fn test_log_reconfigure() {
+    let first = std::env::temp_dir().join(format!("exdisj-{}-reconfigure.log", std::process::id()));
+    let second = first.with_extension("2.log");
+    let moved = first.with_extension("moved.log");
+
+    let logger = Logger::default();
+    logger.open(&first, LoggerLevel::Warning, LoggerRedirect::new(None, false)).unwrap();
+    logger.submit(LoggerWrite::new_str("t".to_string(), "dropped".to_string(), LoggerLevel::Info));
+
+    assert!(logger.set_level(LoggerLevel::Info));
+    logger.submit(LoggerWrite::new_str("t".to_string(), "one".to_string(), LoggerLevel::Info));
+
+    logger.set_path(&second, false).unwrap();
+    logger.submit(LoggerWrite::new_str("t".to_string(), "two".to_string(), LoggerLevel::Info));
+
+    std::fs::rename(&second, &moved).unwrap();
+    logger.reopen().unwrap();
+    logger.submit(LoggerWrite::new_str("t".to_string(), "three".to_string(), LoggerLevel::Info));
+    logger.flush().unwrap();
+
+    assert_eq!(std::fs::read_to_string(&first).unwrap(), "t INFO one\n");
+    assert_eq!(std::fs::read_to_string(&moved).unwrap(), "t INFO two\n");
+    assert_eq!(std::fs::read_to_string(&second).unwrap(), "t INFO three\n");
+
+    assert!(Logger::default().set_path(&first, true).is_err());
+    for path in [first, second, moved] {
+        let _ = std::fs::remove_file(path);
+    }
+}
+
+#[test]
+fn test_log_rate_limit() {
+    let sink = MemorySink::new(LoggerLevel::Debug, 20);
+    let mut logger = LoadedLogger::from_sinks(vec![Box::new(sink.clone())]);
+    logger.set_rate_limiter(Some(
+        RateLimiter::new()
+            .level(LoggerLevel::Error, RateLimit::new(2, Duration::from_secs(60)))
+            .level(LoggerLevel::Warning, RateLimit::dedup_only())
+    )).unwrap();
+
+    for i in 0..5 {
+        let write = LoggerWrite::now(LoggerLevel::Error, format!("connection lost {}", i)).with_location("src/net.rs", 10);
+        log_direct_write(&mut logger, write);
+    }
+    for _ in 0..4 {
+        log_direct(&mut logger, LoggerLevel::Warning, "flapping".to_string());
+    }
+    log_direct(&mut logger, LoggerLevel::Info, "stable".to_string());
+    logger.flush().unwrap();
+
+    let contents = sink.records().iter().map(|x| x.contents().to_string()).collect::<Vec<_>>();
+    assert_eq!(contents, vec![
+        "connection lost 0",
+        "connection lost 1",
+        "flapping",
+        "last message repeated 3 times",
+        "stable",
+        "suppressed 3 messages from src/net.rs:10"
+    ]);
+
+    // If nothing else is logged, the suppressed records are reported once the window ends.
+    let sink = MemorySink::new(LoggerLevel::Debug, 20);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+    logger.set_rate_limiter(Some(RateLimiter::new().all(RateLimit::new(1, Duration::from_millis(50))))).unwrap();
+    let burst = || (0..3).for_each(|_| log_error!(logger: &logger, "timeout"));
+    let summaries = || sink.records().iter().filter(|x| x.contents().starts_with("suppressed 2 messages from")).count();
+
+    burst();
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(summaries(), 0);
+    logger.report_suppressed();
+    assert_eq!(summaries(), 1);
+
+    logger.start_background(4, OverflowPolicy::Block).unwrap();
+    burst();
+    std::thread::sleep(Duration::from_millis(50) + REPORT_INTERVAL * 3);
+    assert_eq!(summaries(), 2);
+    logger.stop_background();
+
+    // A run of repeated messages is reported once it stops, without waiting for a different message.
+    let sink = MemorySink::new(LoggerLevel::Debug, 20);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+    logger.set_rate_limiter(Some(RateLimiter::new().all(RateLimit::dedup_only()))).unwrap();
+    logger.start_background(16, OverflowPolicy::Block).unwrap();
+    for _ in 0..5 {
+        log_warning!(logger: &logger, "flap");
+    }
+    std::thread::sleep(REPORT_INTERVAL * 4);
+
+    let contents = sink.records().iter().map(|x| x.contents().to_string()).collect::<Vec<_>>();
+    assert_eq!(contents, vec!["flap", "last message repeated 4 times"]);
+    logger.stop_background();
+}
+
+#[test]
+fn test_log_console() {
+    let time = "2026-10-18 09:05:03.123456789 +02:00".to_string();
+    let mut write = LoggerWrite::new_str(time, "listening".to_string(), LoggerLevel::Warning);
+    write.push_field("port", &80);
+
+    let redirect = LoggerRedirect::default();
+    assert_eq!(redirect.render(&write, false), "09:05:03.123 WARNING  listening port=80\n");
+    assert_eq!(redirect.render(&write, true), "09:05:03.123 \x1b[33mWARNING \x1b[0m listening port=80\n");
+    assert!(!redirect.clone().with_color(ColorMode::Never).use_color(true));
+    assert_eq!(redirect.render(&LoggerWrite::now(LoggerLevel::Info, String::new()), false).find(' '), Some(12));
+
+    let contents = redirect.clone().with_format(ConsoleFormat::Contents);
+    assert_eq!(contents.render(&write, true), "listening\n");
+    let file = redirect.with_format(ConsoleFormat::File(LogFormat::Text));
+    assert_eq!(file.render(&write, true), write.render(&LogFormat::Text));
+}
+
+#[test]
+fn test_log_panic_hook() {
+    // The hook is process-wide, so the previous one is put back once the panic is caught.
+    let previous = std::panic::take_hook();
+    install_panic_hook(false);
+
+    let capture = LogCapture::start();
+    let result = std::panic::catch_unwind(|| panic!("worker {} failed", 3));
+    assert!(result.is_err());
+    std::panic::set_hook(previous);
+
+    capture.assert_contains(LoggerLevel::Critical, "panicked at");
+    capture.assert_contains(LoggerLevel::Critical, "worker 3 failed");
+    assert_eq!(capture.records()[0].file(), Some(file!()));
+    drop(capture);
+
+    // The record is still written if another thread is briefly using the logger.
+    let sink = MemorySink::new(LoggerLevel::Debug, 10);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+    let busy = std::sync::Barrier::new(2);
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let _lock = logger.access();
+            busy.wait();
+            std::thread::sleep(Duration::from_millis(50));
+        });
+        busy.wait();
+        logger.write_panic(LoggerWrite::now(LoggerLevel::Critical, "thread panicked".to_string()));
+    });
+    assert_eq!(sink.records().len(), 1);
+}
+
+#[test]
+fn test_named_loggers() {
+    let db_sink = MemorySink::new(LoggerLevel::Debug, 10);
+    let net_sink = MemorySink::new(LoggerLevel::Warning, 10);
+
+    let db = named_logger("test-db");
+    db.open_sinks(vec![Box::new(db_sink.clone())]);
+    let net = Logger::named("test-net");
+    net.open_sinks(vec![Box::new(net_sink.clone())]);
+
+    log_debug!(logger: named_logger("test-db"), "query took {}ms", 4);
+    log_info!(logger: &net, "ignored");
+    log_warning!(logger: &net, peer = "10.0.0.1", "connection reset");
+
+    assert_eq!(db_sink.records()[0].contents(), "query took 4ms");
+    assert_eq!(net_sink.records().len(), 1);
+    assert_eq!(net_sink.records()[0].fields()[0].0, "peer");
+
+    // A logger whose file cannot be written to loses only that file, keeping its sinks, without affecting the others.
+    let path = std::env::temp_dir().join(format!("exdisj-{}-readonly.log", std::process::id()));
+    std::fs::write(&path, "").unwrap();
+    let broken = Logger::named("test-broken");
+    let broken_sink = MemorySink::new(LoggerLevel::Debug, 10);
+    broken.pass(LoadedLogger::new(File::open(&path).unwrap(), LoggerLevel::Debug, LoggerRedirect::new(None, false)));
+    broken.add_sink(broken_sink.clone());
+    log_error!(logger: &broken, "lost");
+    log_error!(logger: &broken, "kept");
+
+    assert!(broken.is_open());
+    assert_eq!(broken.modify(|x| x.file().is_none()), Some(true));
+    assert_eq!(broken_sink.records().last().map(|x| x.contents().to_string()), Some("kept".to_string()));
+    assert!(db.is_open() && net.is_open());
+    let _ = std::fs::remove_file(path);
+}
+
+#[test]
+fn test_log_sinks() {
+    let debug = MemorySink::new(LoggerLevel::Debug, 2);
+    let warning = MemorySink::new(LoggerLevel::Warning, 10);
+
+    let mut logger = LoadedLogger::from_sinks(vec![Box::new(debug.clone()), Box::new(warning.clone())]);
+    assert_eq!(logger.min_level(), LoggerLevel::Debug);
+
+    log_direct(&mut logger, LoggerLevel::Debug, "first".to_string());
+    log_direct(&mut logger, LoggerLevel::Warning, "second".to_string());
+    log_direct(&mut logger, LoggerLevel::Info, "third".to_string());
+
+    let contents = |sink: &MemorySink| sink.records().iter().map(|x| x.contents().to_string()).collect::<Vec<_>>();
+    assert_eq!(contents(&debug), vec!["second", "third"]);
+    assert_eq!(contents(&warning), vec!["second"]);
+}
+
+#[test]
+fn test_log_background() {
+    let sink = MemorySink::new(LoggerLevel::Debug, 100);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+    logger.start_background(4, OverflowPolicy::Block).unwrap();
+    assert!(logger.is_background());
+
+    for i in 0..50 {
+        logger.submit(LoggerWrite::now(LoggerLevel::Info, format!("record {}", i)));
+    }
+    logger.flush().unwrap();
+
+    let records = sink.records();
+    assert_eq!(records.len(), 50);
+    assert_eq!(records[49].contents(), "record 49");
+    assert_eq!(logger.dropped(), 0);
+
+    logger.stop_background();
+    assert!(!logger.is_background());
+}
+
+#[test]
+fn test_log_background_failures() {
+    /// Logs back into its own logger when it sees "echo", and panics when it sees "panic".
+    struct EchoSink(Logger);
+    impl Debug for EchoSink {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "EchoSink")
+        }
+    }
+    impl LogSink for EchoSink {
+        fn level(&self) -> LoggerLevel {
+            LoggerLevel::Debug
+        }
+        fn write(&mut self, write: &LoggerWrite) -> Result<(), std::io::Error> {
+            match write.contents() {
+                "echo" => (0..3).for_each(|i| self.0.submit(LoggerWrite::now(LoggerLevel::Info, format!("echoed {i}")))),
+                "panic" => panic!("sink failed"),
+                _ => ()
+            }
+            Ok(())
+        }
+    }
+
+    let sink = MemorySink::new(LoggerLevel::Debug, 100);
+    let logger = Logger::default();
+    logger.open_sinks(vec![Box::new(sink.clone())]);
+    logger.add_sink(EchoSink(logger.clone()));
+    logger.start_background(1, OverflowPolicy::Block).unwrap();
+
+    // The writer cannot wait for space in its own queue, so the echoes that do not fit are dropped.
+    logger.submit(LoggerWrite::now(LoggerLevel::Info, "echo".to_string()));
+    logger.flush().unwrap();
+    assert_eq!(logger.dropped(), 2);
+
+    logger.submit(LoggerWrite::now(LoggerLevel::Info, "panic".to_string()));
+    logger.flush().unwrap();
+    logger.submit(LoggerWrite::now(LoggerLevel::Info, "after".to_string()));
+    logger.flush().unwrap();
+
+    let contents = sink.records().iter().map(|x| x.contents().to_string()).collect::<Vec<_>>();
+    assert_eq!(contents, vec!["echo", "echoed 0", "panic", "after"]);
+    logger.stop_background();
+}
+
+#[test]
+fn test_log_filter() {
+    let filter: LevelFilter = "exdisj::net=warning, myapp::db=debug,info".parse().unwrap();
+    assert_eq!(filter.level_for(Some("exdisj::net")), Some(LoggerLevel::Warning));
+    assert_eq!(filter.level_for(Some("exdisj::net::tcp")), Some(LoggerLevel::Warning));
+    assert_eq!(filter.level_for(Some("exdisj::network")), Some(LoggerLevel::Info));
+    assert_eq!(filter.level_for(None), Some(LoggerLevel::Info));
+    assert!("exdisj=loud".parse::<LevelFilter>().is_err());
+
+    let sink = MemorySink::new(LoggerLevel::Debug, 10);
+    let mut logger = LoadedLogger::from_sinks(vec![Box::new(sink.clone())]);
+    logger.set_filter(Some(filter));
+
+    log_direct_write(&mut logger, LoggerWrite::now(LoggerLevel::Info, "net info".to_string()).with_module("exdisj::net"));
+    log_direct_write(&mut logger, LoggerWrite::now(LoggerLevel::Debug, "db debug".to_string()).with_module("myapp::db"));
+    log_direct_write(&mut logger, LoggerWrite::now(LoggerLevel::Debug, "other debug".to_string()).with_module("myapp"));
+
+    let records = sink.records();
+    assert_eq!(records.len(), 1);
+    assert_eq!(records[0].contents(), "db debug");
+}
+
+#[test]
+fn test_log_rotation() {
+    let path = std::env::temp_dir().join(format!("exdisj-{}-rotation.log", std::process::id()));
+    let rotated = |i: usize| PathBuf::from(format!("{}.{i}", path.display()));
+
+    let mut file = LogFile::open(&path, false).unwrap();
+    file.set_rotation(Some(RotationPolicy::new(2).max_bytes(10)));
+    for i in 0..4 {
+        file.write_all(format!("record {i}\n").as_bytes()).unwrap();
+    }
+    drop(file);
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "record 3\n");
+    assert_eq!(std::fs::read_to_string(rotated(1)).unwrap(), "record 2\n");
+    assert_eq!(std::fs::read_to_string(rotated(2)).unwrap(), "record 1\n");
+    assert!(!rotated(3).exists());
+
+    let mut file = LogFile::open(&path, true).unwrap();
+    file.write_all(b"more\n").unwrap();
+    drop(file);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "record 3\nmore\n");
+
+    for file in [path.clone(), rotated(1), rotated(2)] {
+        std::fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn test_log_rotation_period() {
+    use chrono::TimeZone;
+
+    let at = |day: u32, hour: u32, minute: u32| Local.with_ymd_and_hms(2026, 3, day, hour, minute, 0).unwrap();
+    assert!(!RotationPeriod::Hourly.crossed(&at(1, 9, 0), &at(1, 9, 59)));
+    assert!(RotationPeriod::Hourly.crossed(&at(1, 9, 59), &at(1, 10, 0)));
+    assert!(RotationPeriod::Hourly.crossed(&at(1, 9, 0), &at(2, 9, 0)));
+    assert!(!RotationPeriod::Daily.crossed(&at(1, 0, 0), &at(1, 23, 59)));
+    assert!(RotationPeriod::Daily.crossed(&at(1, 23, 59), &at(2, 0, 0)));
+
+    let path = std::env::temp_dir().join(format!("exdisj-{}-rotation-period.log", std::process::id()));
+    let rotated = PathBuf::from(format!("{}.1", path.display()));
+    let policy = RotationPolicy::new(1).period(RotationPeriod::Daily);
+
+    // A file last written today is kept when appending.
+    std::fs::write(&path, "today\n").unwrap();
+    let mut file = LogFile::open(&path, true).unwrap();
+    file.set_rotation(Some(policy.clone()));
+    file.write_all(b"more\n").unwrap();
+    drop(file);
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "today\nmore\n");
+    assert!(!rotated.exists());
+
+    // A file last written in an earlier period is rotated by the first write, as it was started in that period.
+    std::fs::write(&path, "old\n").unwrap();
+    File::options().write(true).open(&path).unwrap().set_modified(std::time::SystemTime::now() - Duration::from_secs(3 * 24 * 60 * 60)).unwrap();
+    let mut file = LogFile::open(&path, true).unwrap();
+    file.set_rotation(Some(policy));
+    file.write_all(b"new\n").unwrap();
+    file.write_all(b"newer\n").unwrap();
+    drop(file);
+    assert_eq!(std::fs::read_to_string(&rotated).unwrap(), "old\n");
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "new\nnewer\n");
+
+    for file in [path, rotated] {
+        std::fs::remove_file(file).unwrap();
+    }
+}
+
+#[cfg(feature="gzip")]
+#[test]
+fn test_log_rotation_gzip() {
+    use std::io::Read;
+
+    let path = std::env::temp_dir().join(format!("exdisj-{}-rotation-gzip.log", std::process::id()));
+    let rotated = |i: usize| PathBuf::from(format!("{}.{i}.gz", path.display()));
+    let decompress = |path: PathBuf| {
+        let mut contents = String::new();
+        flate2::read::GzDecoder::new(File::open(path).unwrap()).read_to_string(&mut contents).unwrap();
+        contents
+    };
+
+    let mut file = LogFile::open(&path, false).unwrap();
+    file.set_rotation(Some(RotationPolicy::new(2).max_bytes(10).compress(true)));
+    for i in 0..3 {
+        file.write_all(format!("record {i}\n").as_bytes()).unwrap();
+    }
+    drop(file);
+
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), "record 2\n");
+    assert_eq!(decompress(rotated(1)), "record 1\n");
+    assert_eq!(decompress(rotated(2)), "record 0\n");
+    assert!(!PathBuf::from(format!("{}.1", path.display())).exists());
+
+    for file in [path.clone(), rotated(1), rotated(2)] {
+        std::fs::remove_file(file).unwrap();
+    }
+}
@@ -322,12 +322,7 @@ impl<Task> RestartableTask<Task>
             self.restart_count >= self.max_restart
         }
         pub fn restarts_left(&self) -> u8 {
-            if self.restart_count >= self.max_restart {
-                0
-            }
-            else {
-                self.max_restart - self.restart_count
-            }
+            self.max_restart.saturating_sub(self.restart_count)
         }
 }
 